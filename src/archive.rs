@@ -0,0 +1,212 @@
+// 删除前归档：过期文件按日期打包为 tar.gz（附清单 MANIFEST.json），写入归档目录后再删除
+//
+// 归档文件为 {archive.path}/YYYY/YYYY-MM-DD.tar.gz，同一天再次归档时依次加 .1、.2 后缀。
+// 先写入临时文件并落盘，成功后改名并同步所在目录；归档失败时该日期的文件都不删除，留到下次清理重试。
+// 打包是阻塞的文件操作，调用方在 spawn_blocking 中执行。
+// 归档目录有单独的保留天数（archive.retention_days，从归档时算起），不配置时永久保留。
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use chrono::NaiveDate;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::{clock::Clock, durability, layout};
+
+const DEFAULT_ARCHIVE_PATH: &str = "archive";
+const ARCHIVE_SUFFIX: &str = ".tar.gz";
+const MANIFEST_NAME: &str = "MANIFEST.json";
+
+const ARCHIVE_PATH_ERROR: &str = "archive.path 不能为空，且不能位于日志目录内";
+const ARCHIVE_RETENTION_ERROR: &str = "archive.retention_days 必须大于0";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct ArchiveConfig {
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    #[serde(default = "default_archive_path")]
+    pub(crate) path: String, // 归档根目录，可以是较慢的NFS挂载
+    #[serde(default)]
+    pub(crate) retention_days: Option<u32>, // 归档后保留天数，不配置时永久保留
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        ArchiveConfig {
+            enabled: false,
+            path: default_archive_path(),
+            retention_days: None,
+        }
+    }
+}
+
+fn default_archive_path() -> String {
+    DEFAULT_ARCHIVE_PATH.to_string()
+}
+
+pub(crate) fn validate_archive(config: &ArchiveConfig, log_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if !config.enabled {
+        return Ok(());
+    }
+    if config.path.trim().is_empty() || layout::is_within(Path::new(&config.path), Path::new(log_path)) {
+        return Err(ARCHIVE_PATH_ERROR.into());
+    }
+    if config.retention_days == Some(0) {
+        return Err(ARCHIVE_RETENTION_ERROR.into());
+    }
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+struct Manifest {
+    date: String,
+    created: String,
+    source: String,
+    files: Vec<ManifestEntry>,
+}
+
+#[derive(serde::Serialize)]
+struct ManifestEntry {
+    path: String, // 相对于日志根目录
+    start: String,
+    end: String,
+    size: u64,
+}
+
+// 把同一天的文件打包到归档目录，返回归档文件路径
+pub(crate) fn archive_day(
+    config: &ArchiveConfig,
+    root: &Path,
+    day: NaiveDate,
+    files: &[&layout::LayoutFile],
+    clock: &Clock,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = Path::new(&config.path).join(day.format("%Y").to_string());
+    let created = !dir.is_dir();
+    fs::create_dir_all(&dir).map_err(|e| format!("创建归档目录失败 {:?}: {}", dir, e))?;
+
+    let name = day.format("%Y-%m-%d").to_string();
+    let mut target = dir.join(format!("{}{}", name, ARCHIVE_SUFFIX));
+    let mut sequence = 0;
+    while target.exists() {
+        sequence += 1;
+        target = dir.join(format!("{}.{}{}", name, sequence, ARCHIVE_SUFFIX));
+    }
+    let temp = target.with_extension("gz.tmp");
+
+    let result = write_archive(&temp, root, &name, files, clock).and_then(|_| {
+        fs::rename(&temp, &target)?;
+        // 改名后的目录项落盘，之后才删除原文件；新建的年份目录同步其上级目录
+        durability::sync_path(&dir)?;
+        if created {
+            durability::sync_path(Path::new(&config.path))?;
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp);
+        return Err(format!("写入归档失败 {:?}: {}", target, e).into());
+    }
+
+    Ok(target)
+}
+
+fn write_archive(
+    temp: &Path,
+    root: &Path,
+    date: &str,
+    files: &[&layout::LayoutFile],
+    clock: &Clock,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let relative = |file: &layout::LayoutFile| {
+        file.path
+            .strip_prefix(root)
+            .unwrap_or(&file.path)
+            .to_string_lossy()
+            .replace(std::path::MAIN_SEPARATOR, "/")
+    };
+
+    let manifest = Manifest {
+        date: date.to_string(),
+        created: clock.now().to_rfc3339(),
+        source: root.to_string_lossy().into_owned(),
+        files: files
+            .iter()
+            .map(|file| ManifestEntry {
+                path: relative(file),
+                start: file.start.format("%Y-%m-%d %H:%M").to_string(),
+                end: file.end.format("%Y-%m-%d %H:%M").to_string(),
+                size: file.size,
+            })
+            .collect(),
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+
+    let mut builder = tar::Builder::new(GzEncoder::new(File::create(temp)?, Compression::default()));
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(clock.now().timestamp().max(0) as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, MANIFEST_NAME, manifest.as_slice())?;
+
+    for file in files {
+        builder.append_path_with_name(&file.path, relative(file))?;
+    }
+
+    let archive = builder.into_inner()?.finish()?;
+    archive.sync_all()?;
+    Ok(())
+}
+
+// 删除归档时间超过归档保留天数的归档文件，返回删除的文件数
+pub(crate) async fn cleanup_archives(config: &ArchiveConfig) -> usize {
+    let Some(days) = config.retention_days else {
+        return 0;
+    };
+    let Some(cutoff) = SystemTime::now().checked_sub(Duration::from_secs(days as u64 * 86400)) else {
+        return 0;
+    };
+    let Ok(years) = fs::read_dir(&config.path) else {
+        return 0;
+    };
+
+    let mut expired: BTreeSet<PathBuf> = BTreeSet::new();
+    for year in years.flatten() {
+        let Ok(entries) = fs::read_dir(year.path()) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let is_archive = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.ends_with(ARCHIVE_SUFFIX));
+            // 按归档时间（文件修改时间）判断
+            let archived_at = entry.metadata().and_then(|m| m.modified());
+            if is_archive && archived_at.is_ok_and(|time| time < cutoff) {
+                expired.insert(entry.path());
+            }
+        }
+    }
+
+    let mut removed = 0;
+    for path in expired {
+        match fs::remove_file(&path) {
+            Ok(_) => {
+                removed += 1;
+                tklog::async_info!("cleanup|", &format!("已删除过期归档: {:?}", path));
+                // 年份目录变空时一并删除
+                if let Some(parent) = path.parent() {
+                    let _ = fs::remove_dir(parent);
+                }
+            }
+            Err(e) => {
+                tklog::async_error!("cleanup|", &format!("删除归档失败 {:?}: {}", path, e));
+            }
+        }
+    }
+
+    removed
+}
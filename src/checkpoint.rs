@@ -0,0 +1,292 @@
+// 偏移量检查点：跳过Kafka重新投递（重启或分区再均衡后）的已写入记录
+//
+// 每个时间段文件旁有一个检查点（原文件名.offsets），每个写入过该时间段的分区占一行
+// `主题 分区 偏移量 条目序号 副本序号 文件长度`（制表符分隔），记录该分区在这个时间段中最后一次写入的位置。
+// 写入时只更新内存，每条Kafka记录处理完后（flush）把有变化的检查点整体重写：先写临时文件再改名，
+// 文件大小只与分区数有关；除 none 外临时文件在改名前落盘，目录项随下一次同步落盘，早于偏移量提交。
+// 同一分区的写入按前三者依次递增，启动时读取恢复窗口（recovery.window_hours）内所有时间段的检查点，
+// 得到每个分区最后一次写入的位置；之后不超过该位置的写入直接跳过。
+// 一条记录中的部分条目已写入时，重新投递只会补写剩下的条目。
+// 文件长度是写入这一行后时间段文件的长度：检查点可能比日志行先落盘，断电后日志行丢失
+// 或被启动恢复截断时，文件比记录的长度短，这样的行在加载时被忽略，对应分区的记录重新投递时照常写入。
+// 检查点在记录处理完后才重写，进程在两者之间退出时，重新投递会重复写入这条记录。
+// 需要重新消费已写入的偏移量（如重置消费位置、重建主题）时，先删除对应的 .offsets 文件。
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::Duration as ChronoDuration;
+
+use crate::{clock::Clock, durability::Durability, layout, KafkaRecord, LoggingConfig};
+
+// 每个分区最后一次写入的位置
+static HIGH_WATER: Mutex<BTreeMap<(String, i32), Position>> = Mutex::new(BTreeMap::new());
+// 尚未重写到检查点的写入，按时间段分组
+static PENDING: Mutex<BTreeMap<PathBuf, Marks>> = Mutex::new(BTreeMap::new());
+
+// 一个时间段中每个分区最后一次写入的位置，以及写入后时间段文件的长度
+type Marks = BTreeMap<(String, i32), (Position, u64)>;
+
+// 一次写入在分区中的位置：记录偏移量、记录中的条目序号、条目的第几个目的地（0为主路由，之后为复制）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    offset: i64,
+    entry: usize,
+    copy: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Mark<'a> {
+    topic: &'a str,
+    partition: i32,
+    position: Position,
+}
+
+impl<'a> Mark<'a> {
+    pub(crate) fn new(record: &'a KafkaRecord, entry: usize, copy: usize) -> Self {
+        Mark {
+            topic: &record.topic,
+            partition: record.partition,
+            position: Position {
+                offset: record.offset,
+                entry,
+                copy,
+            },
+        }
+    }
+
+    // 是否已在之前写入过
+    pub(crate) fn is_written(&self) -> bool {
+        let high_water = HIGH_WATER.lock().unwrap_or_else(|e| e.into_inner());
+        high_water
+            .get(&(self.topic.to_string(), self.partition))
+            .is_some_and(|last| self.position <= *last)
+    }
+
+    // 日志行写入后调用，end 为写入后时间段文件的长度；检查点在 flush 时重写
+    pub(crate) fn record(&self, segment: &Path, end: u64) {
+        let key = (self.topic.to_string(), self.partition);
+        let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+        pending
+            .entry(segment.to_path_buf())
+            .or_default()
+            .insert(key.clone(), (self.position, end));
+        drop(pending);
+
+        let mut high_water = HIGH_WATER.lock().unwrap_or_else(|e| e.into_inner());
+        high_water.insert(key, self.position);
+    }
+}
+
+// 重写有新写入的时间段的检查点，每条Kafka记录处理完后、偏移量提交前调用；
+// 失败的时间段留到下次重试
+pub(crate) fn flush(durability: &Durability) -> io::Result<()> {
+    let mut pending = std::mem::take(&mut *PENDING.lock().unwrap_or_else(|e| e.into_inner()));
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    // 持有目录树读锁，清理不会在重写期间删除时间段
+    let _tree_guard = layout::write_guard();
+    while let Some((segment, marks)) = pending.pop_first() {
+        if let Err(e) = write_checkpoint(&segment, &marks, durability) {
+            let e = io::Error::new(e.kind(), format!("写入检查点失败 {:?}: {}", segment, e));
+            pending.insert(segment, marks);
+            // 放回时不覆盖期间新写入的位置
+            let mut current = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+            for (segment, marks) in pending {
+                let current = current.entry(segment).or_default();
+                for (key, mark) in marks {
+                    current.entry(key).or_insert(mark);
+                }
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+// 把新的位置合并到时间段已有的检查点后整体替换
+fn write_checkpoint(segment: &Path, marks: &Marks, durability: &Durability) -> io::Result<()> {
+    // 时间段已被清理，不再需要检查点
+    if !segment.exists() {
+        return Ok(());
+    }
+    let path = layout::checkpoint_path(segment);
+    let mut merged = Marks::new();
+    read_checkpoint(&path, None, &mut merged)?;
+    merged.extend(marks.iter().map(|(key, mark)| (key.clone(), *mark)));
+
+    let data: String = merged
+        .iter()
+        .map(|((topic, partition), (position, end))| {
+            format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\n",
+                topic, partition, position.offset, position.entry, position.copy, end
+            )
+        })
+        .collect();
+    durability.replace_file(&layout::checkpoint_temp_path(segment), &path, data.as_bytes())
+}
+
+// 启动时读取日志目录（以及spool目录）中恢复窗口内的时间段的检查点
+pub(crate) async fn load(logging: &LoggingConfig, clock: &Clock, layout: &layout::Layout) {
+    let since = clock.now().naive_local() - ChronoDuration::hours(logging.recovery.window_hours as i64);
+    let mut marks = Marks::new();
+    let mut loaded = 0;
+    for root in logging.roots() {
+        for file in layout.scan(Path::new(root)) {
+            if file.end <= since {
+                continue;
+            }
+            let path = layout::checkpoint_path(&file.path);
+            // 压缩后的文件长度与写入时不同，不做比较（只压缩已结束的时间段）
+            let size = (!file.compressed).then_some(file.size);
+            let mut segment = Marks::new();
+            match read_checkpoint(&path, size, &mut segment) {
+                Ok(true) => loaded += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    tklog::async_error!("checkpoint|", &format!("读取检查点失败 {:?}: {}", path, e));
+                }
+            }
+            // 同一分区可能写入多个时间段，取最后的位置
+            for (key, mark) in segment {
+                let last = marks.entry(key).or_insert(mark);
+                *last = (*last).max(mark);
+            }
+        }
+    }
+
+    let partitions = marks.len();
+    *HIGH_WATER.lock().unwrap_or_else(|e| e.into_inner()) =
+        marks.into_iter().map(|(key, (position, _))| (key, position)).collect();
+    tklog::async_info!(
+        "checkpoint|",
+        &format!("已加载{}个偏移量检查点，共{}个分区", loaded, partitions)
+    );
+}
+
+// 读取一个检查点中每个分区的位置，不存在时返回false；
+// 超出时间段文件当前长度 size 的行，以及没有换行结尾的行（旧版本追加写入时中断）被忽略
+fn read_checkpoint(path: &Path, size: Option<u64>, marks: &mut Marks) -> io::Result<bool> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let complete = data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+
+    for line in String::from_utf8_lossy(&data[..complete]).lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [topic, partition, offset, entry, copy, end] = fields[..] else {
+            continue;
+        };
+        let (Ok(partition), Ok(offset), Ok(entry), Ok(copy), Ok(end)) =
+            (partition.parse(), offset.parse(), entry.parse(), copy.parse(), end.parse::<u64>())
+        else {
+            continue;
+        };
+        if size.is_some_and(|size| end > size) {
+            continue; // 日志行没有落盘
+        }
+        let mark = (Position { offset, entry, copy }, end);
+        let last = marks.entry((topic.to_string(), partition)).or_insert(mark);
+        *last = (*last).max(mark);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::durability::DurabilityConfig;
+    use crate::testutil;
+
+    fn position(offset: i64, entry: usize, copy: usize) -> Position {
+        Position { offset, entry, copy }
+    }
+
+    #[test]
+    fn lines_beyond_segment_size_are_ignored() {
+        let temp = testutil::TempDir::new("checkpoint");
+        let path = temp.join("00.log.offsets");
+        fs::write(&path, "app\t0\t10\t0\t0\t100\napp\t0\t11\t0\t0\t200\napp\t1\t5\t2\t1\t50\napp\t0\t12\t0").unwrap();
+
+        let mut marks = Marks::new();
+        assert!(read_checkpoint(&path, Some(150), &mut marks).unwrap());
+        // 偏移量11的日志行没有落盘，最后写入的位置是10；末尾不完整的行被忽略
+        assert_eq!(marks.get(&("app".to_string(), 0)), Some(&(position(10, 0, 0), 100)));
+        assert_eq!(marks.get(&("app".to_string(), 1)), Some(&(position(5, 2, 1), 50)));
+
+        // 压缩后的时间段不比较长度
+        let mut marks = Marks::new();
+        read_checkpoint(&path, None, &mut marks).unwrap();
+        assert_eq!(marks.get(&("app".to_string(), 0)), Some(&(position(11, 0, 0), 200)));
+    }
+
+    #[test]
+    fn flush_keeps_one_line_per_partition() {
+        let temp = testutil::TempDir::new("checkpoint_flush");
+        let segment = temp.join("00.log");
+        fs::write(&segment, "").unwrap();
+        let durability = Durability::new(&DurabilityConfig::default()).unwrap();
+        let topic = "checkpoint_flush";
+
+        for round in 0..3 {
+            for offset in 0..50 {
+                let record = testutil::record(topic, offset % 2, round * 50 + i64::from(offset), b"");
+                Mark::new(&record, 0, 0).record(&segment, (round * 50 + i64::from(offset)) as u64);
+            }
+            flush(&durability).unwrap();
+        }
+
+        let data = fs::read_to_string(layout::checkpoint_path(&segment)).unwrap();
+        assert_eq!(data, format!("{topic}\t0\t148\t0\t0\t148\n{topic}\t1\t149\t0\t0\t149\n"));
+        assert!(!layout::checkpoint_temp_path(&segment).exists());
+        assert!(Mark::new(&testutil::record(topic, 1, 149, b""), 0, 0).is_written());
+        assert!(!Mark::new(&testutil::record(topic, 1, 149, b""), 0, 1).is_written());
+    }
+
+    #[test]
+    fn flush_skips_removed_segments() {
+        let temp = testutil::TempDir::new("checkpoint_removed");
+        let segment = temp.join("00.log");
+        let durability = Durability::new(&DurabilityConfig::default()).unwrap();
+
+        let record = testutil::record("checkpoint_removed", 0, 1, b"");
+        Mark::new(&record, 0, 0).record(&segment, 10);
+        flush(&durability).unwrap();
+        // 时间段已被清理时不留下孤立的检查点
+        assert!(!layout::checkpoint_path(&segment).exists());
+    }
+
+    #[tokio::test]
+    async fn load_reads_only_the_recovery_window() {
+        testutil::silence_logging();
+        let temp = testutil::TempDir::new("checkpoint_load");
+        let logging = testutil::logging(&format!(
+            "{{ path: {:?}, recovery: {{ window_hours: 2 }} }}",
+            temp.path()
+        ));
+        let clock = Clock::new(&logging.time).unwrap();
+        let layout = layout::Layout::parse(&logging.layout).unwrap();
+        let now = clock.now();
+        let topic = "checkpoint_load";
+
+        // 当前时间段和三天前的时间段，旧时间段的偏移量更大
+        for (hours_ago, offset) in [(0, 5), (72, 9)] {
+            let time = now - ChronoDuration::hours(hours_ago);
+            let segment = temp.join(layout.render(&time, None, |_| None));
+            fs::create_dir_all(segment.parent().unwrap()).unwrap();
+            fs::write(&segment, "0123456789").unwrap();
+            fs::write(layout::checkpoint_path(&segment), format!("{topic}\t0\t{offset}\t0\t0\t10\n")).unwrap();
+        }
+
+        load(&logging, &clock, &layout).await;
+        assert!(Mark::new(&testutil::record(topic, 0, 5, b""), 0, 0).is_written());
+        assert!(!Mark::new(&testutil::record(topic, 0, 6, b""), 0, 0).is_written());
+    }
+}
@@ -0,0 +1,307 @@
+// 清理子命令：预览或立即执行一次定时清理
+//
+// 用法: log_server cleanup --dry-run   只列出将被删除的文件，不做任何修改
+//       log_server cleanup --now       立即执行与定时任务相同的清理（含归档和容量清理）
+//
+// 两者与定时任务使用同一套选择逻辑：先按保留规则选出过期文件（日志目录和spool目录），再对剩余文件按容量上限选择
+// （--dry-run 不考虑归档失败而保留的日期），
+// 输出每个文件的时间段、大小、路径和原因，以及合计释放的字节数。
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::{Duration as ChronoDuration, NaiveDate, NaiveDateTime};
+
+use crate::{archive, clock::Clock, cleanup_old_logs, layout, quota, retention::Retention, Config};
+
+const CLEANUP_USAGE: &str = "用法: log_server cleanup --dry-run | --now";
+
+// 一个被删除（或将被删除）的文件及原因
+#[derive(Debug, Clone)]
+pub(crate) struct Removal {
+    pub(crate) file: layout::LayoutFile,
+    pub(crate) reason: String,
+}
+
+// 按保留规则把文件分为过期和未过期两部分
+// 时间段整体早于期限才算过期，当前正在写入的时间段不会过期
+pub(crate) fn expired_files(
+    files: Vec<layout::LayoutFile>,
+    retention: &Retention,
+    now: NaiveDateTime,
+) -> (Vec<Removal>, Vec<layout::LayoutFile>) {
+    let mut expired = Vec::new();
+    let mut remaining = Vec::new();
+    for file in files {
+        let days = retention.days_for(&file);
+        if file.end <= now - ChronoDuration::days(days as i64) {
+            expired.push(Removal {
+                file,
+                reason: format!("超过保留天数（{}天）", days),
+            });
+        } else {
+            remaining.push(file);
+        }
+    }
+    (expired, remaining)
+}
+
+// 删除选出的文件，返回实际删除的文件和删除的空目录数
+// 按日期分组，启用归档时每组归档成功后才删除，归档失败的日期整组保留到下次重试。
+// 过期清理、按容量清理和磁盘空间不足时的紧急清理都经过这里
+pub(crate) async fn remove_files(
+    root: &Path,
+    archive: &archive::ArchiveConfig,
+    clock: &Clock,
+    removals: Vec<Removal>,
+) -> (Vec<Removal>, usize) {
+    let mut by_day: BTreeMap<NaiveDate, Vec<Removal>> = BTreeMap::new();
+    for removal in removals {
+        by_day.entry(removal.file.start.date()).or_default().push(removal);
+    }
+
+    let mut removed = Vec::new();
+    let mut pruned_count = 0;
+    for (day, removals) in by_day {
+        if archive.enabled {
+            // 打包和落盘是阻塞操作（归档目录可能是较慢的NFS挂载），不占用异步工作线程
+            let files: Vec<layout::LayoutFile> = removals.iter().map(|r| r.file.clone()).collect();
+            let (config, archive_root, archive_clock) = (archive.clone(), root.to_path_buf(), clock.clone());
+            let result = tokio::task::spawn_blocking(move || {
+                let files: Vec<&layout::LayoutFile> = files.iter().collect();
+                archive::archive_day(&config, &archive_root, day, &files, &archive_clock).map_err(|e| e.to_string())
+            })
+            .await
+            .unwrap_or_else(|e| Err(format!("归档任务异常退出: {}", e)));
+            match result {
+                Ok(path) => {
+                    tklog::async_info!(
+                        "cleanup|",
+                        &format!("已归档{}个文件（{}）: {:?}", removals.len(), day, path)
+                    );
+                }
+                Err(e) => {
+                    tklog::async_error!(
+                        "cleanup|",
+                        &format!("归档失败，保留{}的{}个文件: {}", day, removals.len(), e)
+                    );
+                    continue;
+                }
+            }
+        }
+
+        for removal in removals {
+            let file = &removal.file;
+            // 删除文件及变空的父目录
+            match layout::remove_file(root, &file.path) {
+                Ok(pruned) => {
+                    pruned_count += pruned;
+                    tklog::async_info!(
+                        "cleanup|",
+                        &format!(
+                            "{}，已删除: {:?}（{}，{} 字节）",
+                            removal.reason,
+                            file.path,
+                            file.start.format("%Y-%m-%d %H:%M"),
+                            file.size
+                        )
+                    );
+                    removed.push(removal);
+                }
+                Err(e) => {
+                    tklog::async_error!(
+                        "cleanup|",
+                        &format!("删除文件失败 {:?}: {}", file.path, e)
+                    );
+                }
+            }
+        }
+    }
+    (removed, pruned_count)
+}
+
+pub(crate) async fn run_cleanup(config: &Config, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let dry_run = match args {
+        [flag] if flag == "--dry-run" => true,
+        [flag] if flag == "--now" => false,
+        _ => return Err(CLEANUP_USAGE.into()),
+    };
+
+    let logging = &config.logging;
+    let clock = Clock::new(&logging.time)?;
+    let layout = layout::Layout::parse(&logging.layout)?;
+    let retention = Retention::new(logging, &layout)?;
+    let quota = quota::Quota::new(logging)?;
+
+    let mut removals = if dry_run {
+        let now = clock.now().naive_local();
+        let (mut removals, remaining) = expired_files(layout.scan(Path::new(&logging.path)), &retention, now);
+        if let Some(spool) = logging.spool_path() {
+            removals.extend(expired_files(layout.scan(Path::new(spool)), &retention, now).0);
+        }
+        if let Some(ref quota) = quota {
+            for (index, reason) in quota::plan(&remaining, now, quota, &retention) {
+                removals.push(Removal {
+                    file: remaining[index].clone(),
+                    reason,
+                });
+            }
+        }
+        removals
+    } else {
+        let mut removals = cleanup_old_logs(
+            &logging.path,
+            logging.spool_path(),
+            &retention,
+            &logging.archive,
+            &clock,
+            &layout,
+        )
+        .await;
+        if let Some(ref quota) = quota {
+            removals.extend(quota::enforce(&logging.path, &clock, &layout, quota, &retention, &logging.archive).await);
+        }
+        removals
+    };
+    removals.sort_by(|a, b| a.file.start.cmp(&b.file.start).then_with(|| a.file.path.cmp(&b.file.path)));
+
+    if dry_run {
+        println!("清理预览（未删除任何文件，{}）", retention.describe());
+        if logging.archive.enabled {
+            println!("已启用归档：过期文件会先打包到 {}，归档失败的日期不会删除", logging.archive.path);
+        }
+    } else {
+        println!("清理完成（{}）", retention.describe());
+    }
+    for removal in &removals {
+        println!(
+            "  {}  {:>12} 字节  {}  {}",
+            removal.file.start.format("%Y-%m-%d %H:%M"),
+            removal.file.size,
+            removal.file.path.display(),
+            removal.reason
+        );
+    }
+    let total: u64 = removals.iter().map(|r| r.file.size).sum();
+    println!(
+        "合计: {}个文件，{} {} 字节",
+        removals.len(),
+        if dry_run { "可释放" } else { "已释放" },
+        total
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use std::collections::HashMap;
+    use std::fs;
+
+    // 一小时的时间段，end 为时间段结束的时间
+    fn segment(route: Option<&str>, end: &str) -> layout::LayoutFile {
+        let end = NaiveDateTime::parse_from_str(end, "%Y-%m-%d %H:%M").unwrap();
+        layout::LayoutFile {
+            path: format!("{}/{}.log", route.unwrap_or("-"), end).into(),
+            start: end - ChronoDuration::hours(1),
+            end,
+            size: 1,
+            fields: route.map(|r| ("route".to_string(), r.to_string())).into_iter().collect::<HashMap<_, _>>(),
+            compressed: false,
+        }
+    }
+
+    #[test]
+    fn expiry_follows_each_route_retention() {
+        let logging = testutil::logging("{ retention: [{ route: audit, days: 30 }, { route: debug, days: 1 }] }");
+        let layout = layout::Layout::parse(&logging.layout).unwrap();
+        let retention = Retention::new(&logging, &layout).unwrap();
+        let now = NaiveDateTime::parse_from_str("2026-10-18 12:00", "%Y-%m-%d %H:%M").unwrap();
+
+        // 时间段恰好在期限时结束即过期，晚一小时结束的保留
+        let files = vec![
+            segment(Some("api"), "2026-10-11 12:00"),
+            segment(Some("api"), "2026-10-11 13:00"),
+            segment(None, "2026-10-11 12:00"),
+            segment(Some("audit"), "2026-09-18 12:00"),
+            segment(Some("audit"), "2026-09-18 13:00"),
+            segment(Some("audit"), "2026-10-11 12:00"),
+            segment(Some("debug"), "2026-10-17 12:00"),
+            segment(Some("debug"), "2026-10-17 13:00"),
+            // 当前正在写入的时间段
+            segment(Some("debug"), "2026-10-18 13:00"),
+        ];
+        let (expired, remaining) = expired_files(files, &retention, now);
+
+        let expired: Vec<(String, &str)> = expired
+            .iter()
+            .map(|removal| (removal.file.path.display().to_string(), removal.reason.as_str()))
+            .collect();
+        assert_eq!(
+            expired,
+            [
+                ("api/2026-10-11 12:00:00.log".to_string(), "超过保留天数（7天）"),
+                ("-/2026-10-11 12:00:00.log".to_string(), "超过保留天数（7天）"),
+                ("audit/2026-09-18 12:00:00.log".to_string(), "超过保留天数（30天）"),
+                ("debug/2026-10-17 12:00:00.log".to_string(), "超过保留天数（1天）"),
+            ]
+        );
+        let remaining: Vec<String> = remaining.iter().map(|file| file.path.display().to_string()).collect();
+        assert_eq!(
+            remaining,
+            [
+                "api/2026-10-11 13:00:00.log",
+                "audit/2026-09-18 13:00:00.log",
+                "audit/2026-10-11 12:00:00.log",
+                "debug/2026-10-17 13:00:00.log",
+                "debug/2026-10-18 13:00:00.log",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn removal_takes_checkpoints_along() {
+        testutil::silence_logging();
+        let temp = testutil::TempDir::new("cleanup_checkpoints");
+        let archive_dir = testutil::TempDir::new("cleanup_checkpoints_archive");
+        let root = temp.join("logs");
+        let segment = root.join("2026/01/02/03.log");
+        fs::create_dir_all(segment.parent().unwrap()).unwrap();
+        fs::write(&segment, "[2026-01-02 03:00:00] [I] old\n").unwrap();
+        fs::write(layout::checkpoint_path(&segment), "app\t0\t1\t0\t0\t30\n").unwrap();
+        fs::write(layout::checkpoint_temp_path(&segment), "app\t0\t2\t0\t0\t30\n").unwrap();
+
+        let logging = testutil::logging("");
+        let clock = Clock::new(&logging.time).unwrap();
+        let layout = layout::Layout::parse(&logging.layout).unwrap();
+        let archive = archive::ArchiveConfig {
+            enabled: true,
+            path: archive_dir.path().to_string_lossy().into_owned(),
+            retention_days: None,
+        };
+        let files = layout.scan(&root);
+        assert_eq!(files.len(), 1, "检查点不是日志文件");
+        let removals = files
+            .into_iter()
+            .map(|file| Removal { file, reason: String::new() })
+            .collect();
+
+        let (removed, pruned) = remove_files(&root, &archive, &clock, removals).await;
+        assert_eq!(removed.len(), 1);
+        assert_eq!(pruned, 3);
+        assert!(root.is_dir());
+        assert!(fs::read_dir(&root).unwrap().next().is_none(), "检查点随时间段删除");
+
+        // 归档中只有日志文件和清单
+        let archived = archive_dir.join("2026/2026-01-02.tar.gz");
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(fs::File::open(archived).unwrap()));
+        let mut names: Vec<String> = tar
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["2026/01/02/03.log", "MANIFEST.json"]);
+    }
+}
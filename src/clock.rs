@@ -0,0 +1,185 @@
+// 时间设置：时区与时间戳精度
+//
+// 同一个时区用于目录路径（年/月/日/小时）、行时间戳、清理调度和时间参数的解析，
+// 避免不同时区的主机生成不一致的目录树。
+//   timezone  - local（默认）/ UTC / IANA时区名，如 Asia/Shanghai
+//   precision - s（默认）/ ms / us / ns
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::TIMESTAMP_FORMAT;
+
+const TIMEZONE_LOCAL: &str = "local";
+const TIMEZONE_UTC: &str = "UTC";
+const PRECISION_SECONDS: &str = "s";
+
+const PRECISION_ERROR: &str = "time.precision 只能是 s、ms、us 或 ns";
+
+// 支持的无时区时间格式，按配置的时区解释
+const NAIVE_TIME_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct TimeConfig {
+    #[serde(default = "default_timezone")]
+    pub(crate) timezone: String,
+    #[serde(default = "default_precision")]
+    pub(crate) precision: String,
+}
+
+impl Default for TimeConfig {
+    fn default() -> Self {
+        TimeConfig {
+            timezone: default_timezone(),
+            precision: default_precision(),
+        }
+    }
+}
+
+fn default_timezone() -> String {
+    TIMEZONE_LOCAL.to_string()
+}
+
+fn default_precision() -> String {
+    PRECISION_SECONDS.to_string()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Zone {
+    Local,
+    Utc,
+    Named(Tz),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Clock {
+    zone: Zone,
+    timestamp_format: String,
+}
+
+impl Clock {
+    pub(crate) fn new(config: &TimeConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let zone = match config.timezone.as_str() {
+            TIMEZONE_LOCAL => Zone::Local,
+            tz if tz.eq_ignore_ascii_case(TIMEZONE_UTC) => Zone::Utc,
+            tz => Zone::Named(
+                tz.parse::<Tz>()
+                    .map_err(|_| format!("time.timezone 无效: {}（local、UTC 或 IANA时区名）", tz))?,
+            ),
+        };
+
+        let fraction = match config.precision.as_str() {
+            PRECISION_SECONDS => "",
+            "ms" => "%.3f",
+            "us" => "%.6f",
+            "ns" => "%.9f",
+            _ => return Err(PRECISION_ERROR.into()),
+        };
+
+        Ok(Clock {
+            zone,
+            timestamp_format: format!("{}{}", TIMESTAMP_FORMAT, fraction),
+        })
+    }
+
+    pub(crate) fn now(&self) -> DateTime<FixedOffset> {
+        self.convert(Utc::now())
+    }
+
+    // Kafka记录时间戳（毫秒）转换为配置的时区，非法时使用当前时间
+    pub(crate) fn at_millis(&self, timestamp_ms: i64) -> DateTime<FixedOffset> {
+        Utc.timestamp_millis_opt(timestamp_ms)
+            .single()
+            .map(|utc| self.convert(utc))
+            .unwrap_or_else(|| self.now())
+    }
+
+    // UTC时间转换为配置的时区
+    pub(crate) fn convert(&self, utc: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self.zone {
+            Zone::Local => utc.with_timezone(&Local).fixed_offset(),
+            Zone::Utc => utc.fixed_offset(),
+            Zone::Named(tz) => utc.with_timezone(&tz).fixed_offset(),
+        }
+    }
+
+    // 按配置的时区解释本地时间；夏令时重复的时间取较早者，跳过的时间返回None
+    pub(crate) fn resolve_local(&self, naive: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+        match self.zone {
+            Zone::Local => naive.and_local_timezone(Local).earliest().map(|t| t.fixed_offset()),
+            Zone::Utc => Some(naive.and_utc().fixed_offset()),
+            Zone::Named(tz) => tz.from_local_datetime(&naive).earliest().map(|t| t.fixed_offset()),
+        }
+    }
+
+    // 行时间戳，按配置的精度输出小数秒
+    pub(crate) fn format_timestamp(&self, time: &DateTime<FixedOffset>) -> String {
+        time.format(&self.timestamp_format).to_string()
+    }
+
+    // 解析外部传入的时间：RFC3339（带偏移）、无时区时间（按配置的时区）、
+    // 纯日期或Unix时间戳（按位数识别秒/毫秒/微秒/纳秒，秒至少9位即2001年9月之后，更短的数字不是时间）
+    pub(crate) fn parse(&self, value: &str) -> Option<DateTime<FixedOffset>> {
+        let value = value.trim();
+        if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+            return Some(self.convert(time.to_utc()));
+        }
+
+        if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+            let number: i64 = value.parse().ok()?;
+            let utc = match value.len() {
+                0..=8 => None,
+                9..=10 => Utc.timestamp_opt(number, 0).single(),
+                11..=13 => Utc.timestamp_millis_opt(number).single(),
+                14..=16 => Utc.timestamp_micros(number).single(),
+                _ => Some(Utc.timestamp_nanos(number)),
+            }?;
+            return Some(self.convert(utc));
+        }
+
+        let naive = NAIVE_TIME_FORMATS
+            .iter()
+            .find_map(|fmt| NaiveDateTime::parse_from_str(value, fmt).ok())
+            .or_else(|| {
+                NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                    .ok()
+                    .and_then(|d| d.and_hms_opt(0, 0, 0))
+            })?;
+        self.resolve_local(naive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc_clock() -> Clock {
+        Clock::new(&TimeConfig {
+            timezone: TIMEZONE_UTC.to_string(),
+            precision: default_precision(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_epoch_by_digit_count() {
+        let clock = utc_clock();
+        let expected = Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap().fixed_offset();
+        assert_eq!(clock.parse("1792324800"), Some(expected));
+        assert_eq!(clock.parse("1792324800000"), Some(expected));
+        assert_eq!(clock.parse("1792324800000000"), Some(expected));
+        assert_eq!(clock.parse("1792324800000000000"), Some(expected));
+
+        // 年份、紧凑日期等短数字不是时间戳
+        assert_eq!(clock.parse("2026"), None);
+        assert_eq!(clock.parse("20261018"), None);
+        assert_eq!(
+            clock.parse("2026-10-18"),
+            Some(Utc.with_ymd_and_hms(2026, 10, 18, 0, 0, 0).unwrap().fixed_offset())
+        );
+    }
+}
@@ -0,0 +1,317 @@
+// cron表达式：5段（分 时 日 月 周）或6段（秒 分 时 日 月 周），按配置的时区计算
+//
+// 每段支持 *、数字、范围 a-b、列表 a,b 和步长 */n、a-b/n；周 0 和 7 都表示周日。
+// 日和周都不是 * 时，满足其一即可（与标准cron一致）；*/n 等带步长的写法不算 *。
+//
+// 夏令时：
+//   跳过的本地时间 - 小时为 * 或 */n 的任务按实际经过的时间照常执行，不补跑；
+//                    指定了小时的任务在跳过的时间之后立即执行一次
+//   重复的本地时间 - 小时为 * 或 */n 的任务两次都执行；指定了小时的任务只在第一次执行
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, NaiveDateTime, Timelike, Utc};
+
+use crate::clock::Clock;
+
+// 查找下一次执行时间的最大步数，足以覆盖数年内的任意表达式
+const MAX_STEPS: usize = 100_000;
+// 跳过的本地时间最长按3小时处理
+const MAX_GAP_MINUTES: i64 = 180;
+
+#[derive(Debug, Clone)]
+pub(crate) struct CronExpr {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    day_any: bool,
+    weekday_any: bool,
+    hour_any: bool,
+}
+
+impl CronExpr {
+    pub(crate) fn parse(expr: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let (second, rest) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            _ => return Err(format!("cron表达式应为5段或6段: {}", expr).into()),
+        };
+        let context = |e: Box<dyn std::error::Error>| format!("cron表达式无效 {}: {}", expr, e);
+
+        let mut weekdays = parse_field(rest[4], 0, 7).map_err(context)?;
+        // 7 与 0 都表示周日
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        Ok(CronExpr {
+            seconds: parse_field(second, 0, 59).map_err(context)?,
+            minutes: parse_field(rest[0], 0, 59).map_err(context)?,
+            hours: parse_field(rest[1], 0, 23).map_err(context)?,
+            days: parse_field(rest[2], 1, 31).map_err(context)?,
+            months: parse_field(rest[3], 1, 12).map_err(context)?,
+            weekdays,
+            day_any: rest[2] == "*",
+            weekday_any: rest[4] == "*",
+            hour_any: rest[1].starts_with('*'),
+        })
+    }
+
+    // 严格晚于 after 的下一次执行时间
+    pub(crate) fn next_after(&self, after: DateTime<Utc>, clock: &Clock) -> Option<DateTime<Utc>> {
+        let wall_of = |t: DateTime<Utc>| clock.convert(t).naive_local();
+        let mut t = after.with_nanosecond(0)? + ChronoDuration::seconds(1);
+
+        for _ in 0..MAX_STEPS {
+            let wall = wall_of(t);
+
+            // 月、日、小时不匹配时直接跳到下一个月/日/小时的开始
+            if let Some(target) = self.skip_to(wall) {
+                match clock.resolve_local(target) {
+                    Some(next) => t = next.to_utc(),
+                    None => {
+                        // 目标落在跳过的本地时间中
+                        let end = gap_end(target, clock)?;
+                        if !self.hour_any && self.matches_between(target, wall_of(end)) {
+                            return Some(end);
+                        }
+                        t = end;
+                    }
+                }
+                continue;
+            }
+
+            if self.matches(wall) {
+                // 重复的本地时间：指定了小时的任务只在第一次执行
+                let first = clock.resolve_local(wall).map(|time| time.to_utc());
+                if self.hour_any || first == Some(t) {
+                    return Some(t);
+                }
+            }
+
+            // 分钟不匹配时跳到下一分钟，否则逐秒前进
+            let step = if self.minutes & (1 << wall.minute()) == 0 {
+                ChronoDuration::seconds(60 - wall.second() as i64)
+            } else {
+                ChronoDuration::seconds(1)
+            };
+            let next = t + step;
+            let next_wall = wall_of(next);
+            // 跨过了跳过的本地时间，指定了小时的任务在之后立即执行
+            if next_wall - wall > step && !self.hour_any && self.matches_between(wall + step, next_wall) {
+                return Some(next);
+            }
+            t = next;
+        }
+
+        None
+    }
+
+    fn matches(&self, wall: NaiveDateTime) -> bool {
+        self.skip_to(wall).is_none()
+            && self.minutes & (1 << wall.minute()) != 0
+            && self.seconds & (1 << wall.second()) != 0
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.day_any, self.weekday_any) {
+            (true, true) => day && weekday,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    // 月、日或小时不匹配时返回下一个候选时间段的开始
+    fn skip_to(&self, wall: NaiveDateTime) -> Option<NaiveDateTime> {
+        let date = wall.date();
+        if self.months & (1 << date.month()) == 0 {
+            let (year, month) = if date.month() == 12 {
+                (date.year() + 1, 1)
+            } else {
+                (date.year(), date.month() + 1)
+            };
+            return NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0);
+        }
+        if !self.day_matches(date) {
+            return date.succ_opt()?.and_hms_opt(0, 0, 0);
+        }
+        if self.hours & (1 << wall.hour()) == 0 {
+            return wall.date().and_hms_opt(wall.hour(), 0, 0).map(|t| t + ChronoDuration::hours(1));
+        }
+        None
+    }
+
+    // [start, end) 之间是否有匹配的本地时间
+    fn matches_between(&self, start: NaiveDateTime, end: NaiveDateTime) -> bool {
+        let mut wall = start;
+        while wall < end {
+            if self.matches(wall) {
+                return true;
+            }
+            wall += ChronoDuration::seconds(1);
+        }
+        false
+    }
+}
+
+// 跳过的本地时间之后的第一个时刻
+fn gap_end(wall: NaiveDateTime, clock: &Clock) -> Option<DateTime<Utc>> {
+    let start = wall.with_second(0)?;
+    (1..=MAX_GAP_MINUTES)
+        .find_map(|minutes| clock.resolve_local(start + ChronoDuration::minutes(minutes)))
+        .map(|time| time.to_utc())
+}
+
+// 解析一段，返回取值的位图
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut bits = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| format!("无效的步长: {}", item))?;
+                if step == 0 {
+                    return Err(format!("步长必须大于0: {}", item).into());
+                }
+                (range, step)
+            }
+            None => (item, 1),
+        };
+
+        let number = |value: &str| -> Result<u32, Box<dyn std::error::Error>> {
+            let value: u32 = value.parse().map_err(|_| format!("无效的取值: {}", item))?;
+            if !(min..=max).contains(&value) {
+                return Err(format!("取值超出范围 {}-{}: {}", min, max, item).into());
+            }
+            Ok(value)
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // a/n 表示从 a 开始到最大值
+                None if step > 1 => (number(range)?, max),
+                None => {
+                    let value = number(range)?;
+                    (value, value)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("范围起点大于终点: {}", item).into());
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::clock::TimeConfig;
+
+    fn new_york() -> Clock {
+        Clock::new(&TimeConfig {
+            timezone: "America/New_York".to_string(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    // 从 after 开始连续计算 count 次执行时间
+    fn runs(expr: &str, after: DateTime<Utc>, count: usize) -> Vec<DateTime<Utc>> {
+        let clock = new_york();
+        let cron = CronExpr::parse(expr).unwrap();
+        let mut after = after;
+        (0..count)
+            .map(|_| {
+                after = cron.next_after(after, &clock).unwrap();
+                after
+            })
+            .collect()
+    }
+
+    // 2026-03-08 02:00 EST 跳到 03:00 EDT
+    #[test]
+    fn spring_forward() {
+        // 00:30 EST
+        let after = utc(2026, 3, 8, 5, 30);
+        // 每两小时：跳过的 02:00 不补跑，之后按本地时间照常执行
+        assert_eq!(
+            runs("0 */2 * * *", after, 2),
+            vec![utc(2026, 3, 8, 8, 0), utc(2026, 3, 8, 10, 0)]
+        );
+        // 每小时：01:00 EST 之后是 03:00 EDT，中间只经过一小时
+        assert_eq!(
+            runs("0 * * * *", after, 2),
+            vec![utc(2026, 3, 8, 6, 0), utc(2026, 3, 8, 7, 0)]
+        );
+        // 指定 02:30：在跳过的时间之后立即执行一次，第二天照常
+        assert_eq!(
+            runs("30 2 * * *", after, 2),
+            vec![utc(2026, 3, 8, 7, 0), utc(2026, 3, 9, 6, 30)]
+        );
+    }
+
+    // 2026-11-01 02:00 EDT 回到 01:00 EST
+    #[test]
+    fn fall_back() {
+        // 00:30 EDT
+        let after = utc(2026, 11, 1, 4, 30);
+        // 每小时：重复的 01:00 两次都执行
+        assert_eq!(
+            runs("0 * * * *", after, 3),
+            vec![utc(2026, 11, 1, 5, 0), utc(2026, 11, 1, 6, 0), utc(2026, 11, 1, 7, 0)]
+        );
+        // 每两小时：00:00、02:00 EST，重复的 01:00 不匹配
+        assert_eq!(
+            runs("0 */2 * * *", after, 2),
+            vec![utc(2026, 11, 1, 7, 0), utc(2026, 11, 1, 9, 0)]
+        );
+        // 指定 01:30：只在第一次执行
+        assert_eq!(
+            runs("30 1 * * *", after, 2),
+            vec![utc(2026, 11, 1, 5, 30), utc(2026, 11, 2, 6, 30)]
+        );
+    }
+
+    #[test]
+    fn day_and_weekday_steps() {
+        // 00:00 EDT 2026-10-01（周四）之后
+        let after = utc(2026, 10, 1, 4, 0);
+        // 奇数日
+        assert_eq!(
+            runs("0 0 */2 * *", after, 3),
+            vec![utc(2026, 10, 3, 4, 0), utc(2026, 10, 5, 4, 0), utc(2026, 10, 7, 4, 0)]
+        );
+        // 周日、周二、周四、周六
+        assert_eq!(
+            runs("0 0 * * */2", after, 3),
+            vec![utc(2026, 10, 3, 4, 0), utc(2026, 10, 4, 4, 0), utc(2026, 10, 6, 4, 0)]
+        );
+    }
+
+    #[test]
+    fn never_firing_expression() {
+        let clock = new_york();
+        let after = utc(2026, 10, 18, 0, 0);
+        assert!(CronExpr::parse("0 0 31 2 *").unwrap().next_after(after, &clock).is_none());
+        // 2月29日只在闰年
+        assert_eq!(
+            CronExpr::parse("0 0 29 2 *").unwrap().next_after(after, &clock),
+            Some(utc(2028, 2, 29, 5, 0))
+        );
+    }
+}
@@ -0,0 +1,463 @@
+// 磁盘空间保护：定期检查日志目录所在磁盘的剩余空间
+//
+// 剩余空间低于 warn_free 进入警告状态，低于 critical_free 进入严重状态（百分比或容量，如 "10%"、"5GB"）。
+// 状态变化时记录日志，状态和剩余空间随消息统计输出。非正常状态下按 policy 处理：
+//   cleanup - 按保留规则最先到期的顺序删除时间段，直到剩余空间回到警告线以上（与过期清理同一流程，启用归档时先归档）
+//   drop    - 按状态丢弃低级别日志（warn_min_level / critical_min_level 以下）
+//   pause   - 严重状态下暂停从Kafka拉取，空间恢复后继续（未提交的偏移量不会丢失）
+//   spool   - 严重状态下写入备用目录 spool_path
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::time::Duration;
+
+use tokio::time::{interval, sleep};
+
+use crate::{
+    archive::ArchiveConfig, cleanup, clock::Clock, layout, quota, retention::Retention, schedule, stats, LogLevel,
+};
+
+const POLICY_CLEANUP: &str = "cleanup";
+const POLICY_DROP: &str = "drop";
+const POLICY_PAUSE: &str = "pause";
+const POLICY_SPOOL: &str = "spool";
+
+const STATE_NORMAL: u8 = 0;
+const STATE_WARN: u8 = 1;
+const STATE_CRITICAL: u8 = 2;
+
+const DISK_POLICY_ERROR: &str = "disk_guard.policy 只能是 cleanup、drop、pause 或 spool";
+const DISK_SPOOL_ERROR: &str = "disk_guard.policy 为 spool 时必须配置 spool_path";
+const DISK_THRESHOLD_ERROR: &str = "disk_guard.critical_free 必须小于 warn_free（同为百分比或同为容量）";
+const DISK_INTERVAL_ERROR: &str = "disk_guard.interval_secs 必须大于0";
+
+// 当前状态和上次检查时的剩余空间（u64::MAX 表示尚未检查）
+static STATE: AtomicU8 = AtomicU8::new(STATE_NORMAL);
+static FREE_BYTES: AtomicU64 = AtomicU64::new(u64::MAX);
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct DiskGuardConfig {
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    #[serde(default = "default_interval_secs")]
+    pub(crate) interval_secs: u64,
+    #[serde(default = "default_warn_free")]
+    pub(crate) warn_free: String,
+    #[serde(default = "default_critical_free")]
+    pub(crate) critical_free: String,
+    #[serde(default = "default_policy")]
+    pub(crate) policy: String,
+    #[serde(default = "default_warn_min_level")]
+    pub(crate) warn_min_level: String, // drop策略：警告状态下保留的最低级别
+    #[serde(default = "default_critical_min_level")]
+    pub(crate) critical_min_level: String, // drop策略：严重状态下保留的最低级别
+    #[serde(default)]
+    pub(crate) spool_path: Option<String>, // spool策略的备用目录（应位于其他磁盘）
+}
+
+impl Default for DiskGuardConfig {
+    fn default() -> Self {
+        DiskGuardConfig {
+            enabled: false,
+            interval_secs: default_interval_secs(),
+            warn_free: default_warn_free(),
+            critical_free: default_critical_free(),
+            policy: default_policy(),
+            warn_min_level: default_warn_min_level(),
+            critical_min_level: default_critical_min_level(),
+            spool_path: None,
+        }
+    }
+}
+
+fn default_interval_secs() -> u64 {
+    10
+}
+
+fn default_warn_free() -> String {
+    "10%".to_string()
+}
+
+fn default_critical_free() -> String {
+    "5%".to_string()
+}
+
+fn default_policy() -> String {
+    POLICY_CLEANUP.to_string()
+}
+
+fn default_warn_min_level() -> String {
+    "INFO".to_string()
+}
+
+fn default_critical_min_level() -> String {
+    "ERROR".to_string()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Threshold {
+    Percent(f64),
+    Bytes(u64),
+}
+
+impl Threshold {
+    fn parse(value: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match value.trim().strip_suffix('%') {
+            Some(percent) => {
+                let percent: f64 = percent
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("无效的百分比: {}", value))?;
+                if !(0.0..100.0).contains(&percent) {
+                    return Err(format!("百分比必须在0-100之间: {}", value).into());
+                }
+                Ok(Threshold::Percent(percent))
+            }
+            None => Ok(Threshold::Bytes(quota::parse_size(value)?)),
+        }
+    }
+
+    fn bytes(&self, total: u64) -> u64 {
+        match *self {
+            Threshold::Percent(percent) => (total as f64 * percent / 100.0) as u64,
+            Threshold::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Policy {
+    Cleanup,
+    Drop,
+    Pause,
+    Spool,
+}
+
+// 写入前的判定结果
+pub(crate) enum Admission<'a> {
+    Write,
+    Spool(&'a str),
+    Drop,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DiskGuard {
+    interval: Duration,
+    warn: Threshold,
+    critical: Threshold,
+    policy: Policy,
+    warn_min_level: LogLevel,
+    critical_min_level: LogLevel,
+    spool_path: Option<String>,
+}
+
+impl DiskGuard {
+    // 未启用时返回None
+    pub(crate) fn new(config: &DiskGuardConfig) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        if config.interval_secs == 0 {
+            return Err(DISK_INTERVAL_ERROR.into());
+        }
+
+        let warn = Threshold::parse(&config.warn_free).map_err(|e| format!("disk_guard.warn_free: {}", e))?;
+        let critical =
+            Threshold::parse(&config.critical_free).map_err(|e| format!("disk_guard.critical_free: {}", e))?;
+        match (warn, critical) {
+            (Threshold::Percent(w), Threshold::Percent(c)) if c < w => {}
+            (Threshold::Bytes(w), Threshold::Bytes(c)) if c < w => {}
+            _ => return Err(DISK_THRESHOLD_ERROR.into()),
+        }
+
+        let policy = match config.policy.as_str() {
+            POLICY_CLEANUP => Policy::Cleanup,
+            POLICY_DROP => Policy::Drop,
+            POLICY_PAUSE => Policy::Pause,
+            POLICY_SPOOL if config.spool_path.as_deref().is_some_and(|p| !p.is_empty()) => Policy::Spool,
+            POLICY_SPOOL => return Err(DISK_SPOOL_ERROR.into()),
+            _ => return Err(DISK_POLICY_ERROR.into()),
+        };
+
+        let level = |value: &str| {
+            LogLevel::from_str(value).ok_or_else(|| format!("disk_guard: 无效的日志级别 {}", value))
+        };
+
+        Ok(Some(DiskGuard {
+            interval: Duration::from_secs(config.interval_secs),
+            warn,
+            critical,
+            policy,
+            warn_min_level: level(&config.warn_min_level)?,
+            critical_min_level: level(&config.critical_min_level)?,
+            spool_path: config.spool_path.clone(),
+        }))
+    }
+
+    // 按当前状态和策略判定一条日志如何写入
+    pub(crate) fn admit(&self, level: &str) -> Admission<'_> {
+        self.admit_in(STATE.load(Ordering::Relaxed), level)
+    }
+
+    fn admit_in(&self, state: u8, level: &str) -> Admission<'_> {
+        if state == STATE_NORMAL {
+            return Admission::Write;
+        }
+
+        match self.policy {
+            Policy::Drop => {
+                let min_level = if state == STATE_CRITICAL {
+                    &self.critical_min_level
+                } else {
+                    &self.warn_min_level
+                };
+                // 未知级别按INFO处理
+                let level = LogLevel::from_str(level).unwrap_or(LogLevel::Info);
+                if level < *min_level {
+                    stats::add(&stats::DISK_DROPPED, 1);
+                    return Admission::Drop;
+                }
+                Admission::Write
+            }
+            Policy::Spool if state == STATE_CRITICAL => match self.spool_path {
+                Some(ref path) => {
+                    stats::add(&stats::DISK_SPOOLED, 1);
+                    Admission::Spool(path)
+                }
+                None => Admission::Write,
+            },
+            _ => Admission::Write,
+        }
+    }
+
+    // pause策略：严重状态下等待空间恢复
+    pub(crate) async fn wait_writable(&self) {
+        if self.policy != Policy::Pause {
+            return;
+        }
+        while STATE.load(Ordering::Relaxed) == STATE_CRITICAL {
+            sleep(self.interval).await;
+        }
+    }
+
+    fn classify(&self, free: u64, total: u64) -> u8 {
+        if free < self.critical.bytes(total) {
+            STATE_CRITICAL
+        } else if free < self.warn.bytes(total) {
+            STATE_WARN
+        } else {
+            STATE_NORMAL
+        }
+    }
+}
+
+fn state_name(state: u8) -> &'static str {
+    match state {
+        STATE_WARN => "警告",
+        STATE_CRITICAL => "严重",
+        _ => "正常",
+    }
+}
+
+// 状态和剩余空间，尚未检查时返回None
+pub(crate) fn describe() -> Option<String> {
+    let free = FREE_BYTES.load(Ordering::Relaxed);
+    if free == u64::MAX {
+        return None;
+    }
+    Some(format!(
+        "磁盘{}（剩余 {} 字节）",
+        state_name(STATE.load(Ordering::Relaxed)),
+        free
+    ))
+}
+
+// 后台检查任务
+pub(crate) async fn run_monitor(
+    log_path: String,
+    guard: DiskGuard,
+    clock: Clock,
+    layout: layout::Layout,
+    retention: Retention,
+    archive: ArchiveConfig,
+) {
+    let mut ticker = interval(guard.interval);
+    loop {
+        ticker.tick().await;
+
+        let Some((free, total)) = free_space(&log_path).await else {
+            continue;
+        };
+        let mut state = guard.classify(free, total);
+        update_state(state, free).await;
+
+        // cleanup策略：删除最先到期的文件直到回到警告线以上
+        if state != STATE_NORMAL && guard.policy == Policy::Cleanup {
+            let needed = guard.warn.bytes(total).saturating_sub(free);
+            emergency_cleanup(&log_path, &clock, &layout, &retention, &archive, needed).await;
+            if let Some((free, total)) = free_space(&log_path).await {
+                state = guard.classify(free, total);
+                update_state(state, free).await;
+            }
+        }
+    }
+}
+
+async fn free_space(log_path: &str) -> Option<(u64, u64)> {
+    match fs4::statvfs(log_path) {
+        Ok(stats) => Some((stats.available_space(), stats.total_space())),
+        Err(e) => {
+            tklog::async_error!("disk|", &format!("获取磁盘空间失败 {}: {}", log_path, e));
+            None
+        }
+    }
+}
+
+async fn update_state(state: u8, free: u64) {
+    FREE_BYTES.store(free, Ordering::Relaxed);
+    let previous = STATE.swap(state, Ordering::Relaxed);
+    if previous == state {
+        return;
+    }
+
+    let message = format!(
+        "磁盘状态: {} -> {}，剩余 {} 字节",
+        state_name(previous),
+        state_name(state),
+        free
+    );
+    if state > previous {
+        tklog::async_warn!("disk|", &message);
+    } else {
+        tklog::async_info!("disk|", &message);
+    }
+}
+
+// 按保留规则最先到期的顺序选出至少 needed 字节的文件，经归档后删除
+async fn emergency_cleanup(
+    log_path: &str,
+    clock: &Clock,
+    layout: &layout::Layout,
+    retention: &Retention,
+    archive: &ArchiveConfig,
+    needed: u64,
+) {
+    // 与定时的清理、压缩和按需容量清理不同时执行
+    let _tree_jobs = schedule::tree_jobs_guard().await;
+    let root = Path::new(log_path);
+    let now = clock.now().naive_local();
+    let files = layout.scan(root);
+
+    let mut selected = Vec::new();
+    let mut freed = 0;
+    for index in quota::by_expiry(&files, retention) {
+        if freed >= needed {
+            break;
+        }
+        let file = &files[index];
+        if file.end > now {
+            continue; // 正在写入的时间段
+        }
+        freed += file.size;
+        selected.push(cleanup::Removal {
+            file: file.clone(),
+            reason: "磁盘空间不足".to_string(),
+        });
+    }
+
+    let (removed, _) = cleanup::remove_files(root, archive, clock, selected).await;
+    if !removed.is_empty() {
+        tklog::async_warn!(
+            "disk|",
+            &format!("磁盘空间不足，紧急清理删除了{}个文件", removed.len())
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn guard(yaml: &str) -> Result<Option<DiskGuard>, Box<dyn std::error::Error>> {
+        let mut config: DiskGuardConfig = serde_yaml::from_str(yaml).unwrap();
+        config.enabled = true;
+        DiskGuard::new(&config)
+    }
+
+    #[test]
+    fn classify_at_threshold_boundaries() {
+        let percent = guard("{ warn_free: 10%, critical_free: 5% }").unwrap().unwrap();
+        // 低于阈值才进入该状态，恰好等于阈值时不算
+        assert_eq!(percent.classify(100, 1000), STATE_NORMAL);
+        assert_eq!(percent.classify(99, 1000), STATE_WARN);
+        assert_eq!(percent.classify(50, 1000), STATE_WARN);
+        assert_eq!(percent.classify(49, 1000), STATE_CRITICAL);
+        assert_eq!(percent.classify(0, 0), STATE_NORMAL);
+
+        let bytes = guard("{ warn_free: 1MB, critical_free: 512KB }").unwrap().unwrap();
+        assert_eq!(bytes.classify(1 << 20, u64::MAX), STATE_NORMAL);
+        assert_eq!(bytes.classify((1 << 20) - 1, u64::MAX), STATE_WARN);
+        assert_eq!(bytes.classify(512 << 10, u64::MAX), STATE_WARN);
+        assert_eq!(bytes.classify((512 << 10) - 1, u64::MAX), STATE_CRITICAL);
+
+        // 严重阈值必须低于警告阈值，且单位一致
+        for yaml in [
+            "{ warn_free: 5%, critical_free: 5% }",
+            "{ warn_free: 10%, critical_free: 1MB }",
+            "{ warn_free: 100%, critical_free: 5% }",
+            "{ warn_free: 10%, critical_free: 5%, policy: spool }",
+            "{ interval_secs: 0 }",
+        ] {
+            assert!(guard(yaml).is_err(), "{}", yaml);
+        }
+    }
+
+    #[test]
+    fn admit_by_state_and_level() {
+        let drop = guard("{ policy: drop, warn_min_level: INFO, critical_min_level: ERROR }").unwrap().unwrap();
+        let admitted = |state, level| matches!(drop.admit_in(state, level), Admission::Write);
+
+        assert!(admitted(STATE_NORMAL, "TRACE"));
+        // 警告状态保留 INFO 及以上，未知级别按 INFO
+        assert!(!admitted(STATE_WARN, "DEBUG"));
+        assert!(admitted(STATE_WARN, "INFO"));
+        assert!(admitted(STATE_WARN, "LOUD"));
+        // 严重状态保留 ERROR 及以上
+        assert!(!admitted(STATE_CRITICAL, "WARN"));
+        assert!(!admitted(STATE_CRITICAL, "LOUD"));
+        assert!(admitted(STATE_CRITICAL, "ERROR"));
+        assert!(admitted(STATE_CRITICAL, "FATAL"));
+
+        // spool 只在严重状态改写到备用目录
+        let spool = guard("{ policy: spool, spool_path: /spool }").unwrap().unwrap();
+        assert!(matches!(spool.admit_in(STATE_WARN, "DEBUG"), Admission::Write));
+        assert!(matches!(spool.admit_in(STATE_CRITICAL, "DEBUG"), Admission::Spool("/spool")));
+
+        // cleanup 和 pause 不影响单条写入
+        for policy in ["cleanup", "pause"] {
+            let guard = guard(&format!("{{ policy: {} }}", policy)).unwrap().unwrap();
+            assert!(matches!(guard.admit_in(STATE_CRITICAL, "TRACE"), Admission::Write));
+        }
+    }
+
+    #[tokio::test]
+    async fn emergency_cleanup_waits_for_tree_jobs() {
+        testutil::silence_logging();
+        let temp = testutil::TempDir::new("disk_lock");
+        let logging = testutil::logging(&format!("path: {:?}", temp.path()));
+        let layout = layout::Layout::parse(&logging.layout).unwrap();
+        let retention = Retention::new(&logging, &layout).unwrap();
+        let clock = Clock::new(&logging.time).unwrap();
+
+        // 定时任务执行期间紧急清理只能等待
+        let guard = schedule::tree_jobs_guard().await;
+        let handle = tokio::spawn(async move {
+            emergency_cleanup(&logging.path, &clock, &layout, &retention, &logging.archive, u64::MAX).await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!handle.is_finished());
+
+        drop(guard);
+        handle.await.unwrap();
+    }
+}
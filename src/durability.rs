@@ -0,0 +1,240 @@
+// 落盘策略：写入后何时调用 fsync，以及与偏移量提交的配合
+//
+//   none        - 不主动落盘（默认），由操作系统决定，断电可能丢失最近几秒已提交的日志
+//   per-batch   - 每条Kafka记录（一批条目）写完后，同步本批写过的文件，再提交偏移量
+//   interval    - 每隔 interval_ms 同步一次写过的文件，偏移量在同步之后才提交
+//   per-message - 每个条目写入后立即同步
+// 除 none 外，偏移量提交时对应的日志（以及死信文件）一定已经落盘；
+// 新建的文件同时同步所在目录，新建的每一级目录同步其上级目录。
+// 每次同步的耗时计入 stats 的 fsync 延迟直方图。
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::stats;
+
+const MODE_NONE: &str = "none";
+const MODE_PER_BATCH: &str = "per-batch";
+const MODE_INTERVAL: &str = "interval";
+const MODE_PER_MESSAGE: &str = "per-message";
+
+const DURABILITY_MODE_ERROR: &str = "durability.mode 只能是 none、per-batch、interval 或 per-message";
+const DURABILITY_INTERVAL_ERROR: &str = "durability.interval_ms 必须大于0";
+
+// 已写入但尚未同步的文件和目录，以及上次同步的时间
+static DIRTY: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+static LAST_SYNC: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct DurabilityConfig {
+    #[serde(default = "default_mode")]
+    pub(crate) mode: String,
+    #[serde(default = "default_interval_ms")]
+    pub(crate) interval_ms: u64, // interval模式的同步间隔
+}
+
+impl Default for DurabilityConfig {
+    fn default() -> Self {
+        DurabilityConfig {
+            mode: default_mode(),
+            interval_ms: default_interval_ms(),
+        }
+    }
+}
+
+fn default_mode() -> String {
+    MODE_NONE.to_string()
+}
+
+fn default_interval_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    None,
+    PerBatch,
+    Interval,
+    PerMessage,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Durability {
+    mode: Mode,
+    interval: Duration,
+}
+
+impl Durability {
+    pub(crate) fn new(config: &DurabilityConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mode = match config.mode.as_str() {
+            MODE_NONE => Mode::None,
+            MODE_PER_BATCH => Mode::PerBatch,
+            MODE_INTERVAL if config.interval_ms > 0 => Mode::Interval,
+            MODE_INTERVAL => return Err(DURABILITY_INTERVAL_ERROR.into()),
+            MODE_PER_MESSAGE => Mode::PerMessage,
+            _ => return Err(DURABILITY_MODE_ERROR.into()),
+        };
+        Ok(Durability {
+            mode,
+            interval: Duration::from_millis(config.interval_ms),
+        })
+    }
+
+    // 写入方在写完一个条目后调用（持有文件句柄时）
+    pub(crate) fn after_write(&self, file: &File, path: &Path, written: usize) -> io::Result<()> {
+        if self.mode == Mode::None {
+            return Ok(());
+        }
+        // 文件长度等于本次写入的长度，说明文件是新建的，目录项也需要落盘
+        let created = file.metadata()?.len() == written as u64;
+        let parent = path.parent().filter(|_| created);
+
+        if self.mode == Mode::PerMessage {
+            timed_sync(|| file.sync_data())?;
+            return self.mark_dirty(parent);
+        }
+        self.mark_dirty(std::iter::once(path).chain(parent))
+    }
+
+    // 创建目录及缺少的上级目录；新建的每一级目录都要同步其上级目录，目录项才算落盘
+    pub(crate) fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        if self.mode == Mode::None || dir.is_dir() {
+            return fs::create_dir_all(dir);
+        }
+
+        // 从最近的已存在的上级目录开始，之后的每一级都是新建的
+        let mut missing = Vec::new();
+        let mut current = Some(dir);
+        while let Some(path) = current.filter(|p| !p.as_os_str().is_empty() && !p.exists()) {
+            missing.push(path);
+            current = path.parent();
+        }
+        fs::create_dir_all(dir)?;
+
+        let parents = missing
+            .iter()
+            .filter_map(|path| path.parent())
+            .map(|parent| if parent.as_os_str().is_empty() { Path::new(".") } else { parent });
+        self.mark_dirty(parents)
+    }
+
+    // 先写入临时文件再改名替换 path，读取方只会看到完整的旧内容或新内容；
+    // 除 none 外临时文件在改名前落盘，改名后的目录项按落盘策略同步
+    pub(crate) fn replace_file(&self, temp: &Path, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut file = File::create(temp)?;
+        file.write_all(data)?;
+        if self.mode != Mode::None {
+            timed_sync(|| file.sync_data())?;
+        }
+        drop(file);
+        fs::rename(temp, path)?;
+
+        if self.mode == Mode::None {
+            return Ok(());
+        }
+        let parent = path
+            .parent()
+            .map(|parent| if parent.as_os_str().is_empty() { Path::new(".") } else { parent });
+        self.mark_dirty(parent)
+    }
+
+    // per-message模式立即同步，其他模式记录到下一次同步
+    fn mark_dirty<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) -> io::Result<()> {
+        if self.mode == Mode::PerMessage {
+            for path in paths {
+                sync_path(path)?;
+            }
+            return Ok(());
+        }
+        let mut dirty = DIRTY.lock().unwrap_or_else(|e| e.into_inner());
+        dirty.extend(paths.into_iter().map(Path::to_path_buf));
+        Ok(())
+    }
+
+    // 是否可以提交偏移量：interval模式要等到下一次同步
+    pub(crate) fn commit_ready(&self) -> bool {
+        if self.mode != Mode::Interval {
+            return true;
+        }
+        let mut last_sync = LAST_SYNC.lock().unwrap_or_else(|e| e.into_inner());
+        let last = *last_sync.get_or_insert_with(Instant::now);
+        last.elapsed() >= self.interval
+    }
+
+    // 同步所有写过的文件和新建文件所在的目录，失败的路径留到下次重试
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        if matches!(self.mode, Mode::None | Mode::PerMessage) {
+            return Ok(());
+        }
+        let paths = std::mem::take(&mut *DIRTY.lock().unwrap_or_else(|e| e.into_inner()));
+
+        let mut result = Ok(());
+        let mut failed = BTreeSet::new();
+        for path in paths {
+            if let Err(e) = sync_path(&path) {
+                if result.is_ok() {
+                    result = Err(io::Error::new(e.kind(), format!("同步失败 {:?}: {}", path, e)));
+                }
+                failed.insert(path);
+            }
+        }
+        if !failed.is_empty() {
+            DIRTY.lock().unwrap_or_else(|e| e.into_inner()).append(&mut failed);
+        }
+
+        *LAST_SYNC.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+        result
+    }
+}
+
+// 文件和目录都以只读方式打开后同步
+pub(crate) fn sync_path(path: &Path) -> io::Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        // 同步前已被清理或压缩
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    timed_sync(|| file.sync_all())
+}
+
+fn timed_sync(sync: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+    let start = Instant::now();
+    let result = sync();
+    stats::record_fsync(start.elapsed());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn new_directories_mark_every_parent() {
+        let temp = testutil::TempDir::new("durability");
+        let base = temp.path().to_path_buf();
+        let durability = Durability::new(&DurabilityConfig {
+            mode: MODE_PER_BATCH.to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        durability.create_dir_all(&base.join("2026/10/18")).unwrap();
+        // 待同步集合是进程级的，只检查本测试创建的路径
+        let parents = [base.clone(), base.join("2026"), base.join("2026/10")];
+        {
+            let dirty = DIRTY.lock().unwrap();
+            for parent in &parents {
+                assert!(dirty.contains(parent), "{:?}", parent);
+            }
+            assert!(!dirty.contains(&base.join("2026/10/18")));
+        }
+        durability.sync().unwrap();
+        let dirty = DIRTY.lock().unwrap();
+        assert!(parents.iter().all(|parent| !dirty.contains(parent)));
+    }
+}
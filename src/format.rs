@@ -0,0 +1,413 @@
+// 日志行格式：多行内容处理策略，以及按策略还原原始内容的读取工具
+//
+// 行格式为 `[时间] [级别] 内容`，内容中的换行按策略处理：
+//   raw    - 原样写入（默认，多行内容会占用多个物理行）
+//   escape - 转义为 \n、\r，反斜杠转义为 \\
+//   indent - 续行以标记开头（默认 "\t| "）
+//   framed - 长度前缀: `[时间] [级别] #字节数 内容`，内容原样写入（开启 sanitize 时换行与 raw 一样被转义）
+//
+// 用法: log_server read <文件>... [--grep <正则>] [--level <级别>]
+use std::borrow::Cow;
+use std::fs;
+
+use regex::Regex;
+
+use crate::{Config, LogLevel};
+
+const MULTILINE_RAW: &str = "raw";
+const MULTILINE_ESCAPE: &str = "escape";
+const MULTILINE_INDENT: &str = "indent";
+const MULTILINE_FRAMED: &str = "framed";
+const DEFAULT_MULTILINE_MARKER: &str = "\t| ";
+
+const MULTILINE_POLICY_ERROR: &str = "multiline.policy 只能是 raw、escape、indent 或 framed";
+const MULTILINE_MARKER_ERROR: &str = "multiline.marker 不能为空，且不能以 [ 开头或包含换行";
+const READ_USAGE: &str = "用法: log_server read <文件>... [--grep <正则>] [--level <级别>]";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct MultilineConfig {
+    #[serde(default = "default_multiline_policy")]
+    pub(crate) policy: String,
+    #[serde(default = "default_multiline_marker")]
+    pub(crate) marker: String, // indent策略的续行标记
+}
+
+impl Default for MultilineConfig {
+    fn default() -> Self {
+        MultilineConfig {
+            policy: default_multiline_policy(),
+            marker: default_multiline_marker(),
+        }
+    }
+}
+
+fn default_multiline_policy() -> String {
+    MULTILINE_RAW.to_string()
+}
+
+fn default_multiline_marker() -> String {
+    DEFAULT_MULTILINE_MARKER.to_string()
+}
+
+pub(crate) fn validate_multiline(config: &MultilineConfig) -> Result<(), Box<dyn std::error::Error>> {
+    match config.policy.as_str() {
+        MULTILINE_RAW | MULTILINE_ESCAPE | MULTILINE_FRAMED => Ok(()),
+        MULTILINE_INDENT
+            if !config.marker.is_empty()
+                && !config.marker.starts_with('[')
+                && !config.marker.contains(['\n', '\r']) =>
+        {
+            Ok(())
+        }
+        MULTILINE_INDENT => Err(MULTILINE_MARKER_ERROR.into()),
+        _ => Err(MULTILINE_POLICY_ERROR.into()),
+    }
+}
+
+// 该策略自己处理内容中的换行，清洗时保留换行
+// framed 按长度前缀读回（read 子命令、启动恢复），续行中伪造的 `[时间] [级别]` 不会被当作记录
+pub(crate) fn handles_newlines(config: &MultilineConfig) -> bool {
+    matches!(config.policy.as_str(), MULTILINE_ESCAPE | MULTILINE_INDENT | MULTILINE_FRAMED)
+}
+
+// framed策略的记录可能跨越多行，只能从文件开头按长度前缀解析
+pub(crate) fn is_framed(config: &MultilineConfig) -> bool {
+    config.policy == MULTILINE_FRAMED
+}
+
+// 按策略生成完整的一条记录（含结尾换行）
+pub(crate) fn format_line(
+    timestamp: &str,
+    abbreviation: &str,
+    content: &str,
+    config: &MultilineConfig,
+) -> String {
+    format!("[{}] [{}] {}\n", timestamp, abbreviation, encode_content(content, config))
+}
+
+// 按策略编码内容中的换行；framed策略加上 #字节数 前缀
+pub(crate) fn encode_content<'a>(content: &'a str, config: &MultilineConfig) -> Cow<'a, str> {
+    match config.policy.as_str() {
+        MULTILINE_ESCAPE => escape_content(content),
+        MULTILINE_INDENT if content.contains('\n') => {
+            Cow::Owned(content.replace('\n', &format!("\n{}", config.marker)))
+        }
+        MULTILINE_FRAMED => Cow::Owned(format!("#{} {}", content.len(), content)),
+        _ => Cow::Borrowed(content),
+    }
+}
+
+fn escape_content(content: &str) -> Cow<'_, str> {
+    if !content.contains(['\\', '\n', '\r']) {
+        return Cow::Borrowed(content);
+    }
+
+    let mut escaped = String::with_capacity(content.len() + 8);
+    for c in content.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            other => escaped.push(other),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+fn unescape_content(content: &str) -> String {
+    let mut unescaped = String::with_capacity(content.len());
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            // 其他转义（如清洗产生的 \x1b）原样保留
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+// 还原后的一条记录
+#[derive(Debug)]
+pub(crate) struct ParsedRecord {
+    pub(crate) timestamp: String,
+    pub(crate) abbreviation: String,
+    pub(crate) content: String,
+}
+
+// 按策略把文件内容还原为记录；无法识别的行作为上一条记录的延续（raw策略）或跳过
+pub(crate) fn parse_records(data: &str, config: &MultilineConfig) -> Vec<ParsedRecord> {
+    let mut records: Vec<ParsedRecord> = Vec::new();
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let line_end = rest.find('\n').map(|i| i + 1).unwrap_or(rest.len());
+        let line = rest[..line_end].trim_end_matches('\n');
+
+        let Some((timestamp, abbreviation, content)) = split_prefix(line) else {
+            // 续行
+            if let Some(last) = records.last_mut() {
+                match config.policy.as_str() {
+                    MULTILINE_INDENT => {
+                        if let Some(continuation) = line.strip_prefix(config.marker.as_str()) {
+                            last.content.push('\n');
+                            last.content.push_str(continuation);
+                        }
+                    }
+                    MULTILINE_RAW => {
+                        last.content.push('\n');
+                        last.content.push_str(line);
+                    }
+                    _ => {}
+                }
+            }
+            rest = &rest[line_end..];
+            continue;
+        };
+
+        if config.policy == MULTILINE_FRAMED {
+            // 长度前缀: #字节数 内容；格式不符时（如切换策略前写入的普通行）按普通行处理
+            if let Frame::Complete { start, len } = frame_at(rest.as_bytes()) {
+                records.push(ParsedRecord {
+                    timestamp: timestamp.to_string(),
+                    abbreviation: abbreviation.to_string(),
+                    content: rest[start..start + len].to_string(),
+                });
+                rest = &rest[start + len + 1..];
+                continue;
+            }
+        }
+
+        let content = match config.policy.as_str() {
+            MULTILINE_ESCAPE => unescape_content(content),
+            _ => content.to_string(),
+        };
+        records.push(ParsedRecord {
+            timestamp: timestamp.to_string(),
+            abbreviation: abbreviation.to_string(),
+            content,
+        });
+        rest = &rest[line_end..];
+    }
+
+    records
+}
+
+// 文件开头完整记录的总字节数，之后的部分是写入中断留下的不完整记录
+// framed策略按长度前缀跳过内容，内容恰好截断在其中的换行处也能识别；
+// 不符合 format_line 写出的格式的行（如切换策略前写入的普通行）按换行处理
+pub(crate) fn complete_len(data: &[u8], framed: bool) -> usize {
+    if !framed {
+        return data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    }
+
+    let mut pos = 0;
+    while pos < data.len() {
+        pos = match frame_at(&data[pos..]) {
+            Frame::Complete { start, len } => pos + start + len + 1,
+            Frame::Torn => return pos,
+            Frame::Invalid => match data[pos..].iter().position(|&b| b == b'\n') {
+                Some(line_len) => pos + line_len + 1,
+                None => return pos, // 最后一行没有换行
+            },
+        };
+    }
+    pos
+}
+
+// data 开头的一条framed记录
+enum Frame {
+    Complete { start: usize, len: usize }, // 内容的起始位置和字节数，内容之后紧跟换行
+    Torn,                                  // 格式正确，但内容或结尾的换行还没有写完
+    Invalid,                               // 不是 format_line 写出的framed记录
+}
+
+fn frame_at(data: &[u8]) -> Frame {
+    let line_end = data.iter().position(|&b| b == b'\n').unwrap_or(data.len());
+    let Some((start, len)) = frame_header(&data[..line_end]) else {
+        return Frame::Invalid;
+    };
+    let end = start + len;
+    let Some(body) = data.get(start..end) else {
+        return Frame::Torn;
+    };
+    if std::str::from_utf8(body).is_err() {
+        return Frame::Invalid;
+    }
+    match data.get(end) {
+        Some(b'\n') => Frame::Complete { start, len },
+        Some(_) => Frame::Invalid,
+        None => Frame::Torn,
+    }
+}
+
+// format_line 写出的首行 `[时间] [级别缩写] #字节数 `：返回内容在行内的起始位置和字节数
+fn frame_header(line: &[u8]) -> Option<(usize, usize)> {
+    // 内容可能在本行被截断，只需要前面有效的UTF-8部分
+    let text = match std::str::from_utf8(line) {
+        Ok(text) => text,
+        Err(e) => std::str::from_utf8(&line[..e.valid_up_to()]).ok()?,
+    };
+    let (timestamp, abbreviation, content) = split_prefix(text)?;
+    if !is_line_timestamp(timestamp) || LogLevel::from_alias(abbreviation)?.to_abbreviation() != abbreviation {
+        return None;
+    }
+    let (len, _) = content.strip_prefix('#')?.split_once(' ')?;
+    // 写入时的十进制长度没有多余的前导零
+    if len.is_empty() || !len.bytes().all(|b| b.is_ascii_digit()) || (len.len() > 1 && len.starts_with('0')) {
+        return None;
+    }
+    let body_len = len.parse::<usize>().ok()?;
+    Some((text.len() - content.len() + len.len() + 2, body_len))
+}
+
+// 行时间戳 `YYYY-MM-DD HH:MM:SS`，可带 .小数秒
+fn is_line_timestamp(timestamp: &str) -> bool {
+    let (seconds, fraction) = timestamp.split_at(timestamp.len().min(19));
+    let digits_at = |s: &str, positions: &[usize]| positions.iter().all(|&i| s.as_bytes()[i].is_ascii_digit());
+    seconds.len() == 19
+        && digits_at(seconds, &[0, 1, 2, 3, 5, 6, 8, 9, 11, 12, 14, 15, 17, 18])
+        && seconds.as_bytes()[4] == b'-'
+        && seconds.as_bytes()[7] == b'-'
+        && seconds.as_bytes()[10] == b' '
+        && seconds.as_bytes()[13] == b':'
+        && seconds.as_bytes()[16] == b':'
+        && (fraction.is_empty()
+            || fraction
+                .strip_prefix('.')
+                .is_some_and(|f| (1..=9).contains(&f.len()) && f.bytes().all(|b| b.is_ascii_digit())))
+}
+
+// 拆分 `[时间] [级别] 内容`
+fn split_prefix(line: &str) -> Option<(&str, &str, &str)> {
+    let rest = line.strip_prefix('[')?;
+    let (timestamp, rest) = rest.split_once("] [")?;
+    let (abbreviation, content) = rest.split_once("] ")?;
+    if abbreviation.is_empty() || abbreviation.len() > 5 {
+        return None;
+    }
+    Some((timestamp, abbreviation, content))
+}
+
+// 读取日志文件并输出还原后的原始内容
+pub(crate) fn run_read(config: &Config, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut grep = None;
+    let mut level = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--grep" => {
+                let pattern = iter.next().ok_or_else(|| format!("--grep 缺少取值\n{}", READ_USAGE))?;
+                grep = Some(Regex::new(pattern).map_err(|e| format!("--grep 正则无效: {}", e))?);
+            }
+            "--level" => {
+                let value = iter.next().ok_or_else(|| format!("--level 缺少取值\n{}", READ_USAGE))?;
+                level = Some(
+                    LogLevel::from_alias(value).ok_or_else(|| format!("无效的日志级别: {}", value))?,
+                );
+            }
+            _ if arg.starts_with("--") => return Err(format!("未知参数: {}\n{}", arg, READ_USAGE).into()),
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.is_empty() {
+        return Err(READ_USAGE.into());
+    }
+
+    let multiline = &config.logging.multiline;
+    for file in &files {
+        let bytes = fs::read(file).map_err(|e| format!("读取日志文件失败: {} - {}", file, e))?;
+        let data = String::from_utf8_lossy(&bytes);
+
+        for record in parse_records(&data, multiline) {
+            if level
+                .as_ref()
+                .is_some_and(|level| level.to_abbreviation() != record.abbreviation)
+            {
+                continue;
+            }
+            if grep.as_ref().is_some_and(|grep| !grep.is_match(&record.content)) {
+                continue;
+            }
+            println!("[{}] [{}] {}", record.timestamp, record.abbreviation, record.content);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed() -> MultilineConfig {
+        MultilineConfig {
+            policy: MULTILINE_FRAMED.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn line(content: &str) -> String {
+        format_line("2026-10-18 12:00:00", "I", content, &framed())
+    }
+
+    #[test]
+    fn complete_len_torn_header() {
+        let complete = line("first");
+        for cut in ["[2026-10-18 12:0", "[2026-10-18 12:00:00] [I] #1", "[2026-10-18 12:00:00] [I] #12 par"] {
+            let data = format!("{}{}", complete, cut);
+            assert_eq!(complete_len(data.as_bytes(), true), complete.len(), "{:?}", cut);
+        }
+    }
+
+    #[test]
+    fn complete_len_body_cut_at_embedded_newline() {
+        let complete = line("one\ntwo");
+        let torn = line("three\n[2026-10-18 12:00:00] [I] looks complete\nfour");
+        // 截断在内容中的换行之后，按换行判断会误以为记录完整
+        let cut = torn.find("looks complete\n").unwrap() + "looks complete\n".len();
+        let data = format!("{}{}", complete, &torn[..cut]);
+
+        assert_eq!(complete_len(data.as_bytes(), true), complete.len());
+        assert_eq!(complete_len(data.as_bytes(), false), data.len());
+        let full = format!("{}{}", complete, torn);
+        assert_eq!(complete_len(full.as_bytes(), true), full.len());
+    }
+
+    #[test]
+    fn complete_len_mixed_with_plain_lines() {
+        // 切换策略前写入的普通行、启动日志等没有长度前缀的行按换行处理
+        let data = format!(
+            "[2026-10-18 11:59:59] [I] plain line\nno prefix at all\n{}[2026-10-18 12:00:01] [W] plain again\n",
+            line("multi\nline")
+        );
+        assert_eq!(complete_len(data.as_bytes(), true), data.len());
+
+        let torn = format!("{}[2026-10-18 12:00:02] [I] no newline yet", data);
+        assert_eq!(complete_len(torn.as_bytes(), true), data.len());
+    }
+
+    #[test]
+    fn complete_len_plain_line_resembling_a_frame() {
+        // 普通行的内容恰好以 #数字 开头时，长度与内容对不上，不能当作长度前缀跳过后面的记录
+        let old = "[2026-10-18 11:59:59] [I] #12 foo\n[2026-10-18 11:59:59] [I] #007 bar\n";
+        let data = format!("{}{}{}", old, line("multi\nline"), line("next"));
+        assert_eq!(complete_len(data.as_bytes(), true), data.len());
+
+        let records = parse_records(&data, &framed());
+        let contents: Vec<&str> = records.iter().map(|r| r.content.as_str()).collect();
+        assert_eq!(contents, ["#12 foo", "#007 bar", "multi\nline", "next"]);
+    }
+}
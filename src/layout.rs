@@ -0,0 +1,550 @@
+// 目录布局：strftime时间字段 + 占位符组成的相对路径模板
+//
+// 例如默认的 `%Y/%m/%d/%H.log`、`{service}/%Y-%m-%d/%H.log`、`%Y%m%d/{level}.log`
+//   时间字段 - %Y %m %d %H（按配置的时区），%% 表示字面的 %
+//   占位符   - {route} {service} {level} {topic} {host}，取值按路由目录名规则清洗，取不到时为 _default
+// 模板中没有 {route} 时，路由目录放在最前面（与未配置布局时一致）。
+//
+// 同一个布局既用于生成写入路径，也用于从已有文件的路径反推时间段，
+// 清理等功能通过 scan 遍历文件，不需要各自实现目录结构。压缩任务生成的 .gz 文件同样会被识别，
+// 时间段旁的偏移量检查点（.offsets）不会被当作日志文件，随时间段一起删除。
+//
+// 写入方在创建目录到写完文件期间持有目录树读锁，删除文件和清理空目录时持有写锁，
+// 避免清理删掉刚创建、尚未写入的目录。
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, FixedOffset, NaiveDate, NaiveDateTime};
+use regex::Regex;
+
+use crate::{sanitize_route_name, DEFAULT_ROUTE_FALLBACK};
+
+const DEFAULT_LAYOUT: &str = "%Y/%m/%d/%H.log";
+const LAYOUT_FIELDS: [&str; 5] = ["route", "service", "level", "topic", "host"];
+pub(crate) const COMPRESSED_SUFFIX: &str = ".gz";
+pub(crate) const CHECKPOINT_SUFFIX: &str = ".offsets";
+
+const LAYOUT_YEAR_ERROR: &str = "logging.layout 必须包含 %Y，且 %H 需要 %d、%d 需要 %m";
+const LAYOUT_COMPONENT_ERROR: &str = "logging.layout 的每一级路径都不能为空、. 或 ..，且不能以 / 开头";
+
+static TREE_LOCK: RwLock<()> = RwLock::new(());
+
+// 写入方持有，期间清理不会删除任何文件或目录
+pub(crate) fn write_guard() -> RwLockReadGuard<'static, ()> {
+    TREE_LOCK.read().unwrap_or_else(|e| e.into_inner())
+}
+
+// 压缩等维护任务替换文件时持有，期间写入方等待
+pub(crate) fn maintenance_guard() -> RwLockWriteGuard<'static, ()> {
+    TREE_LOCK.write().unwrap_or_else(|e| e.into_inner())
+}
+
+// 删除一个文件，并自下而上删除因此变空的父目录（不含日志根目录）
+// 返回删除的空目录数
+pub(crate) fn remove_file(root: &Path, path: &Path) -> io::Result<usize> {
+    let _guard = TREE_LOCK.write().unwrap_or_else(|e| e.into_inner());
+    fs::remove_file(path)?;
+    // 检查点（以及改名前中断留下的临时文件）可能不存在
+    let _ = fs::remove_file(checkpoint_path(path));
+    let _ = fs::remove_file(checkpoint_temp_path(path));
+
+    let mut pruned = 0;
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if current == root || !current.starts_with(root) {
+            break;
+        }
+        // 目录非空时删除失败，停止向上
+        if fs::remove_dir(current).is_err() {
+            break;
+        }
+        pruned += 1;
+        dir = current.parent();
+    }
+
+    Ok(pruned)
+}
+
+// 时间段文件的偏移量检查点路径，压缩前后共用同一个
+pub(crate) fn checkpoint_path(segment: &Path) -> PathBuf {
+    let path = segment.to_string_lossy();
+    let original = path.strip_suffix(COMPRESSED_SUFFIX).unwrap_or(&path);
+    PathBuf::from(format!("{}{}", original, CHECKPOINT_SUFFIX))
+}
+
+// 重写检查点时的临时文件，同样以 .offsets 结尾，scan 不会把它当作日志文件
+pub(crate) fn checkpoint_temp_path(segment: &Path) -> PathBuf {
+    let path = segment.to_string_lossy();
+    let original = path.strip_suffix(COMPRESSED_SUFFIX).unwrap_or(&path);
+    PathBuf::from(format!("{}.tmp{}", original, CHECKPOINT_SUFFIX))
+}
+
+// path 是否位于 root 之内（或就是 root）
+// 两者先解析为绝对路径：已存在的部分解析符号链接，尚不存在的部分按字面处理 . 和 ..
+pub(crate) fn is_within(path: &Path, root: &Path) -> bool {
+    resolve_path(path).starts_with(resolve_path(root))
+}
+
+fn resolve_path(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut resolved = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            other => {
+                resolved.push(other);
+                if let Ok(canonical) = resolved.canonicalize() {
+                    resolved = canonical;
+                }
+            }
+        }
+    }
+    resolved
+}
+
+pub(crate) fn default_layout() -> String {
+    DEFAULT_LAYOUT.to_string()
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Time(char),
+    Field(String),
+}
+
+// 文件名中时间字段能表示的最小时间段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Granularity {
+    Year,
+    Month,
+    Day,
+    Hour,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Layout {
+    components: Vec<Vec<Part>>,
+    implicit_route: bool, // 模板中没有 {route}，路由目录放在最前面
+    pattern: Regex,       // 匹配相对路径并提取时间字段和占位符
+    granularity: Granularity,
+}
+
+// 布局中的一个已有文件及其时间段（配置时区的本地时间）
+#[derive(Debug, Clone)]
+pub(crate) struct LayoutFile {
+    pub(crate) path: PathBuf,
+    pub(crate) start: NaiveDateTime,
+    pub(crate) end: NaiveDateTime,
+    pub(crate) size: u64,
+    pub(crate) fields: HashMap<String, String>, // 路径中占位符的取值，如 service、route
+    pub(crate) compressed: bool,                // 压缩任务生成的 .gz 文件
+}
+
+impl LayoutFile {
+    // 所属服务：布局中的 {service}，没有时为路由目录
+    pub(crate) fn service(&self) -> String {
+        self.fields
+            .get("service")
+            .or_else(|| self.fields.get("route"))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl Layout {
+    pub(crate) fn parse(template: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if template.starts_with('/') || template.contains('\\') {
+            return Err(LAYOUT_COMPONENT_ERROR.into());
+        }
+
+        let mut components = Vec::new();
+        for component in template.split('/') {
+            if component.is_empty() || component == "." || component == ".." {
+                return Err(LAYOUT_COMPONENT_ERROR.into());
+            }
+            components.push(parse_component(component)?);
+        }
+
+        let has = |spec: char| {
+            components
+                .iter()
+                .flatten()
+                .any(|part| matches!(part, Part::Time(c) if *c == spec))
+        };
+        let granularity = match (has('Y'), has('m'), has('d'), has('H')) {
+            (true, true, true, true) => Granularity::Hour,
+            (true, true, true, false) => Granularity::Day,
+            (true, true, false, false) => Granularity::Month,
+            (true, false, false, false) => Granularity::Year,
+            _ => return Err(LAYOUT_YEAR_ERROR.into()),
+        };
+
+        let implicit_route = !components
+            .iter()
+            .flatten()
+            .any(|part| matches!(part, Part::Field(name) if name == "route"));
+        let pattern = build_pattern(&components, implicit_route)?;
+
+        Ok(Layout {
+            components,
+            implicit_route,
+            pattern,
+            granularity,
+        })
+    }
+
+    // 模板中是否包含某个占位符
+    pub(crate) fn has_field(&self, name: &str) -> bool {
+        self.components
+            .iter()
+            .flatten()
+            .any(|part| matches!(part, Part::Field(field) if field == name))
+    }
+
+    // 生成相对于日志根目录的文件路径
+    pub(crate) fn render(
+        &self,
+        time: &DateTime<FixedOffset>,
+        route: Option<&str>,
+        field: impl Fn(&str) -> Option<String>,
+    ) -> PathBuf {
+        let mut path = PathBuf::new();
+        if self.implicit_route {
+            if let Some(route) = route {
+                path.push(route);
+            }
+        }
+
+        for component in &self.components {
+            let mut rendered = String::new();
+            for part in component {
+                match part {
+                    Part::Literal(text) => rendered.push_str(text),
+                    Part::Time(spec) => rendered.push_str(&time.format(&format!("%{}", spec)).to_string()),
+                    Part::Field(name) => {
+                        let value = match name.as_str() {
+                            "route" => route.map(str::to_string),
+                            _ => field(name),
+                        };
+                        rendered.push_str(
+                            &value
+                                .as_deref()
+                                .and_then(sanitize_route_name)
+                                .unwrap_or_else(|| DEFAULT_ROUTE_FALLBACK.to_string()),
+                        );
+                    }
+                }
+            }
+            path.push(rendered);
+        }
+
+        path
+    }
+
+    // 从相对路径反推时间段 [start, end) 和占位符取值，不符合布局的路径返回None
+    fn parse_path(
+        &self,
+        relative: &str,
+    ) -> Option<(NaiveDateTime, NaiveDateTime, HashMap<String, String>)> {
+        let captures = self.pattern.captures(relative)?;
+        let number = |name: &str, default: u32| {
+            captures
+                .name(name)
+                .map_or(Some(default), |m| m.as_str().parse::<u32>().ok())
+        };
+
+        let year = captures.name("Y")?.as_str().parse::<i32>().ok()?;
+        let start = NaiveDate::from_ymd_opt(year, number("m", 1)?, number("d", 1)?)?
+            .and_hms_opt(number("H", 0)?, 0, 0)?;
+        let end = match self.granularity {
+            Granularity::Hour => start + ChronoDuration::hours(1),
+            Granularity::Day => start + ChronoDuration::days(1),
+            Granularity::Month => {
+                let (year, month) = if start.month() == 12 {
+                    (start.year() + 1, 1)
+                } else {
+                    (start.year(), start.month() + 1)
+                };
+                NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?
+            }
+            Granularity::Year => NaiveDate::from_ymd_opt(start.year() + 1, 1, 1)?.and_hms_opt(0, 0, 0)?,
+        };
+
+        let fields = self
+            .pattern
+            .capture_names()
+            .flatten()
+            .filter_map(|name| {
+                let field = name.strip_prefix("f_")?;
+                Some((field.to_string(), captures.name(name)?.as_str().to_string()))
+            })
+            .collect();
+
+        Some((start, end, fields))
+    }
+
+    // 遍历日志根目录下所有符合布局的文件
+    pub(crate) fn scan(&self, root: &Path) -> Vec<LayoutFile> {
+        let mut files = Vec::new();
+        // 隐式路由目录多一级
+        let max_depth = self.components.len() + usize::from(self.implicit_route);
+        self.scan_dir(root, root, max_depth, &mut files);
+        files
+    }
+
+    fn scan_dir(&self, root: &Path, dir: &Path, depth: usize, files: &mut Vec<LayoutFile>) {
+        if depth == 0 {
+            return;
+        }
+        let Ok(entries) = fs::read_dir(dir) else {
+            return; // 跳过无法读取的目录
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                self.scan_dir(root, &path, depth - 1, files);
+                continue;
+            }
+            if !file_type.is_file() {
+                continue;
+            }
+
+            let Some(relative) = path
+                .strip_prefix(root)
+                .ok()
+                .and_then(|p| p.to_str())
+                .map(|p| p.replace(std::path::MAIN_SEPARATOR, "/"))
+            else {
+                continue;
+            };
+            if relative.ends_with(CHECKPOINT_SUFFIX) {
+                continue;
+            }
+            // 压缩后的文件在原路径后加 .gz
+            let (relative, compressed) = match relative.strip_suffix(COMPRESSED_SUFFIX) {
+                Some(original) => (original, true),
+                None => (relative.as_str(), false),
+            };
+            if let Some((start, end, fields)) = self.parse_path(relative) {
+                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                files.push(LayoutFile {
+                    path,
+                    start,
+                    end,
+                    size,
+                    fields,
+                    compressed,
+                });
+            }
+        }
+    }
+}
+
+fn parse_component(component: &str) -> Result<Vec<Part>, Box<dyn std::error::Error>> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = component.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => match chars.next() {
+                Some('%') => literal.push('%'),
+                Some(spec @ ('Y' | 'm' | 'd' | 'H')) => {
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Time(spec));
+                }
+                other => {
+                    return Err(format!(
+                        "logging.layout 不支持的时间字段: %{}（支持 %Y %m %d %H）",
+                        other.map(String::from).unwrap_or_default()
+                    )
+                    .into())
+                }
+            },
+            '{' => {
+                let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                if !LAYOUT_FIELDS.contains(&name.as_str()) {
+                    return Err(format!("logging.layout 未知占位符: {{{}}}", name).into());
+                }
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Field(name));
+            }
+            other => literal.push(other),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+
+    Ok(parts)
+}
+
+// 同名字段只在第一次出现时捕获，之后只做格式匹配
+fn build_pattern(components: &[Vec<Part>], implicit_route: bool) -> Result<Regex, regex::Error> {
+    let mut captured: Vec<String> = Vec::new();
+    let mut capture = |name: String, body: &str| {
+        if captured.contains(&name) {
+            format!("(?:{})", body)
+        } else {
+            let group = format!("(?P<{}>{})", name, body);
+            captured.push(name);
+            group
+        }
+    };
+
+    let mut pattern = String::from("^");
+    if implicit_route {
+        pattern.push_str("(?:(?P<f_route>[^/]+)/)?");
+    }
+    for (index, component) in components.iter().enumerate() {
+        if index > 0 {
+            pattern.push('/');
+        }
+        for part in component {
+            match part {
+                Part::Literal(text) => pattern.push_str(&regex::escape(text)),
+                Part::Time('Y') => pattern.push_str(&capture("Y".to_string(), r"\d{4}")),
+                Part::Time(spec) => pattern.push_str(&capture(spec.to_string(), r"\d{2}")),
+                Part::Field(name) => pattern.push_str(&capture(format!("f_{}", name), "[^/]+")),
+            }
+        }
+    }
+    pattern.push('$');
+
+    Regex::new(&pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn is_within_resolves_dots_and_symlinks() {
+        let temp = testutil::TempDir::new("within");
+        let base = temp.path();
+        let logs = base.join("logs");
+        fs::create_dir_all(&logs).unwrap();
+
+        assert!(is_within(&base.join("other/../logs/archive"), &logs));
+        assert!(is_within(&base.join("./logs/./archive"), &base.join("logs/")));
+        assert!(!is_within(&base.join("logs/../archive"), &logs));
+        assert!(!is_within(&base.join("logs2"), &logs));
+
+        // 指向日志目录的符号链接
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&logs, base.join("link")).unwrap();
+            assert!(is_within(&base.join("link/archive"), &logs));
+        }
+    }
+
+    fn time(value: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(value).unwrap()
+    }
+
+    fn at(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn rendered_paths_scan_back() {
+        let temp = testutil::TempDir::new("layout_round_trip");
+        let fields = |name: &str| match name {
+            "service" => Some("billing".to_string()),
+            "level" => Some("ERROR".to_string()),
+            _ => None,
+        };
+        // 模板、路由、渲染出的路径、时间段、占位符取值
+        let hour = ("2026-12-31 23:00", "2027-01-01 00:00");
+        let cases = [
+            ("%Y/%m/%d/%H.log", Some("api"), "api/2026/12/31/23.log", hour, vec![("route", "api")]),
+            ("%Y/%m/%d/%H.log", None, "2026/12/31/23.log", hour, vec![]),
+            (
+                "{service}/%Y-%m-%d/%H.{level}.log",
+                Some("api"),
+                "api/billing/2026-12-31/23.ERROR.log",
+                hour,
+                vec![("route", "api"), ("service", "billing"), ("level", "ERROR")],
+            ),
+            (
+                "%Y%m%d/{route}.log",
+                None,
+                "20261231/_default.log",
+                ("2026-12-31 00:00", "2027-01-01 00:00"),
+                vec![("route", "_default")],
+            ),
+            ("%Y/%m.log", None, "2026/12.log", ("2026-12-01 00:00", "2027-01-01 00:00"), vec![]),
+            ("%Y.log", None, "2026.log", ("2026-01-01 00:00", "2027-01-01 00:00"), vec![]),
+        ];
+
+        for (index, (template, route, expected, (start, end), expected_fields)) in cases.into_iter().enumerate() {
+            let layout = Layout::parse(template).unwrap();
+            let relative = layout.render(&time("2026-12-31T23:30:00+08:00"), route, fields);
+            assert_eq!(relative, Path::new(expected), "{}", template);
+
+            let root = temp.join(index.to_string());
+            let path = root.join(&relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "line\n").unwrap();
+            fs::write(checkpoint_path(&path), "").unwrap();
+
+            let files = layout.scan(&root);
+            assert_eq!(files.len(), 1, "{}", template);
+            let file = &files[0];
+            assert_eq!(file.path, path);
+            assert_eq!((file.start, file.end), (at(start), at(end)), "{}", template);
+            assert_eq!(file.size, 5);
+            assert!(!file.compressed);
+            let mut fields: Vec<(&str, &str)> = file.fields.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            let mut expected_fields = expected_fields;
+            fields.sort();
+            expected_fields.sort();
+            assert_eq!(fields, expected_fields, "{}", template);
+
+            // 压缩后的文件同样识别
+            fs::rename(&path, format!("{}{}", path.display(), COMPRESSED_SUFFIX)).unwrap();
+            let files = layout.scan(&root);
+            assert_eq!(files.len(), 1);
+            assert!(files[0].compressed);
+            assert_eq!(files[0].start, at(start));
+        }
+    }
+
+    #[test]
+    fn removal_prunes_empty_parents_but_keeps_the_root() {
+        let temp = testutil::TempDir::new("layout_prune");
+        let root = temp.join("logs");
+        let old = root.join("api/2026/01/02/03.log");
+        let sibling = root.join("api/2026/01/03/00.log");
+        for path in [&old, &sibling] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "line\n").unwrap();
+        }
+
+        // 只有 01/02 变空，01 中还有其他日期
+        assert_eq!(remove_file(&root, &old).unwrap(), 1);
+        assert!(!root.join("api/2026/01/02").exists());
+        assert!(root.join("api/2026/01/03").is_dir());
+
+        // 最后一个文件删除后上溯到根目录为止
+        assert_eq!(remove_file(&root, &sibling).unwrap(), 4);
+        assert!(root.is_dir());
+        assert!(fs::read_dir(&root).unwrap().next().is_none());
+    }
+}
@@ -0,0 +1,188 @@
+// 消息大小限制：解码前限制整条记录，写入前限制日志内容
+//
+// 超限时按 oversize_action 处理：
+//   truncate    - 截断并追加可见的截断标记及原始字节数；整条记录超限时不解码，只保留载荷的前 max_record_bytes
+//                 字节作为一个条目的内容（文本来源照常识别级别，其他来源使用默认级别）。
+//                 msgpack/protobuf 载荷截断后不是可读的文本，整条记录超限时写入死信
+//   dead_letter - 原始数据写入死信目录 {dead_letter_path}/{主题}/{分区}-{偏移量}[-{条目}].dat，不写入日志
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::{durability::Durability, sanitize_route_name, stats, KafkaRecord};
+
+const OVERSIZE_TRUNCATE: &str = "truncate";
+const OVERSIZE_DEAD_LETTER: &str = "dead_letter";
+const DEFAULT_DEAD_LETTER_PATH: &str = "dead_letter";
+
+const OVERSIZE_ACTION_ERROR: &str = "limits.oversize_action 只能是 truncate 或 dead_letter";
+const LIMIT_ZERO_ERROR: &str = "limits.max_record_bytes 和 limits.max_content_bytes 必须大于0";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct LimitsConfig {
+    #[serde(default)]
+    pub(crate) max_record_bytes: Option<usize>, // 解码前整条记录的上限
+    #[serde(default)]
+    pub(crate) max_content_bytes: Option<usize>, // 写入前单条日志内容的上限
+    #[serde(default = "default_oversize_action")]
+    pub(crate) oversize_action: String,
+    #[serde(default = "default_dead_letter_path")]
+    pub(crate) dead_letter_path: String,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_record_bytes: None,
+            max_content_bytes: None,
+            oversize_action: default_oversize_action(),
+            dead_letter_path: default_dead_letter_path(),
+        }
+    }
+}
+
+fn default_oversize_action() -> String {
+    OVERSIZE_TRUNCATE.to_string()
+}
+
+fn default_dead_letter_path() -> String {
+    DEFAULT_DEAD_LETTER_PATH.to_string()
+}
+
+pub(crate) fn validate_limits(limits: &LimitsConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if limits.max_record_bytes == Some(0) || limits.max_content_bytes == Some(0) {
+        return Err(LIMIT_ZERO_ERROR.into());
+    }
+    match limits.oversize_action.as_str() {
+        OVERSIZE_TRUNCATE | OVERSIZE_DEAD_LETTER => Ok(()),
+        _ => Err(OVERSIZE_ACTION_ERROR.into()),
+    }
+}
+
+// 单条内容的检查结果
+pub(crate) enum Oversize {
+    Fits,
+    Truncated(String), // 截断后的内容（含截断标记）
+    DeadLetter,
+}
+
+// 整条记录的检查结果
+pub(crate) enum RecordOversize {
+    Fits,
+    Truncate(usize), // 不解码，载荷截断到该字节数后作为一个条目
+    DeadLetter,
+}
+
+// 检查整条记录的大小；binary 为来源格式是否为 msgpack/protobuf
+pub(crate) fn check_record(limits: &LimitsConfig, record: &KafkaRecord, binary: bool) -> RecordOversize {
+    let Some(max) = limits.max_record_bytes else {
+        return RecordOversize::Fits;
+    };
+    if record.payload.len() <= max {
+        return RecordOversize::Fits;
+    }
+
+    stats::record_oversize(&record.topic);
+    if limits.oversize_action == OVERSIZE_DEAD_LETTER || binary {
+        return RecordOversize::DeadLetter;
+    }
+    RecordOversize::Truncate(max)
+}
+
+// 整条记录超限时截断原始载荷：保留前 max 字节（不在多字节字符中间截断），无效的UTF-8按替换字符显示
+pub(crate) fn truncate_payload(payload: &[u8], max: usize) -> String {
+    let mut end = max.min(payload.len());
+    if let Err(e) = std::str::from_utf8(&payload[..end]) {
+        // 只有末尾的字符不完整时回退到字符边界
+        if e.error_len().is_none() {
+            end = e.valid_up_to();
+        }
+    }
+    with_marker(&String::from_utf8_lossy(&payload[..end]), payload.len())
+}
+
+// 检查单条日志内容的大小
+pub(crate) fn check_content(limits: &LimitsConfig, topic: &str, content: &str) -> Oversize {
+    let Some(max) = limits.max_content_bytes else {
+        return Oversize::Fits;
+    };
+    if content.len() <= max {
+        return Oversize::Fits;
+    }
+
+    stats::record_oversize(topic);
+    if limits.oversize_action == OVERSIZE_DEAD_LETTER {
+        return Oversize::DeadLetter;
+    }
+    Oversize::Truncated(truncate_content(content, max, content.len()))
+}
+
+// 在字符边界截断，并追加截断标记和原始字节数
+fn truncate_content(content: &str, max: usize, original_len: usize) -> String {
+    let mut end = max.min(content.len());
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    with_marker(&content[..end], original_len)
+}
+
+fn with_marker(kept: &str, original_len: usize) -> String {
+    format!("{} ...[已截断，原始 {} 字节]", kept, original_len)
+}
+
+// 把超限数据写入死信目录
+pub(crate) fn write_dead_letter(
+    limits: &LimitsConfig,
+    record: &KafkaRecord,
+    entry_index: Option<usize>,
+    data: &[u8],
+    durability: &Durability,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let mut path = PathBuf::from(&limits.dead_letter_path);
+    path.push(sanitize_route_name(&record.topic).unwrap_or_else(|| "_unknown".to_string()));
+    durability
+        .create_dir_all(&path)
+        .map_err(|e| format!("创建死信目录失败: {}，目录: {:?}", e, path))?;
+
+    let file_name = match entry_index {
+        Some(index) => format!("{}-{}-{}.dat", record.partition, record.offset, index),
+        None => format!("{}-{}.dat", record.partition, record.offset),
+    };
+    path.push(file_name);
+    // 与日志一样按落盘策略同步，提交偏移量前死信已经落盘
+    File::create(&path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            durability.after_write(&file, &path, data.len())
+        })
+        .map_err(|e| format!("写入死信文件失败: {}，文件: {:?}", e, path))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn oversized_records_truncate_before_decoding() {
+        let limits: LimitsConfig = serde_yaml::from_str("max_record_bytes: 8").unwrap();
+        let payload = br#"{"L":"ERROR","S":"0123456789"}"#;
+        let record = testutil::record("app", 0, 7, payload);
+
+        assert!(matches!(check_record(&limits, &record, false), RecordOversize::Truncate(8)));
+        // 二进制格式截断后不是可读的文本
+        assert!(matches!(check_record(&limits, &record, true), RecordOversize::DeadLetter));
+
+        // 不解码，只保留载荷的前8字节
+        let content = truncate_payload(payload, 8);
+        assert_eq!(content, r#"{"L":"ER ...[已截断，原始 30 字节]"#);
+        assert_eq!(content.len(), 8 + " ...[已截断，原始 30 字节]".len());
+
+        // 不在多字节字符中间截断（每个汉字3字节）
+        let content = truncate_payload("日志内容".as_bytes(), 8);
+        assert_eq!(content, "日志 ...[已截断，原始 12 字节]");
+        assert_eq!(content.len(), 6 + " ...[已截断，原始 12 字节]".len());
+    }
+}
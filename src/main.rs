@@ -1,11 +1,37 @@
-use chrono::{DateTime, Duration as ChronoDuration, Local};
+use chrono::{DateTime, FixedOffset};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::io::Write;
+use std::io::{Seek, Write};
 use std::net::SocketAddr;
 use std::time::Duration;
-use std::time::SystemTime;
 use tokio::time::{interval, sleep};
 
+mod archive;
+mod checkpoint;
+mod cleanup;
+mod clock;
+mod cron;
+mod disk;
+mod durability;
+mod format;
+mod layout;
+mod limits;
+mod maintenance;
+mod message;
+mod output;
+mod quota;
+mod recovery;
+mod replay;
+mod retention;
+mod rules;
+mod sanitize;
+mod schedule;
+mod stats;
+#[cfg(test)]
+mod testutil;
+
+use message::KafkaMessage;
+
 // 静态字符串常量，避免重复创建
 const LEVEL_TRACE: &str = "TRACE";
 const LEVEL_DEBUG: &str = "DEBUG";
@@ -22,6 +48,10 @@ const LEVEL_ABBR_ERROR: &str = "E";
 const LEVEL_ABBR_FATAL: &str = "F";
 
 const DEFAULT_LOG_PATH: &str = "logs";
+const DEFAULT_ENRICHMENT_FORMAT: &str = "[{topic}:{partition}@{offset} key={key}{headers}]";
+const DEFAULT_ROUTE_BY: &str = "topic";
+const DEFAULT_ROUTE_FALLBACK: &str = "_default";
+const MAX_ROUTE_NAME_LEN: usize = 64;
 const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
 const CLEANUP_TIME_ERROR_MSG: &str =
@@ -30,9 +60,12 @@ const RETENTION_DAYS_ERROR: &str = "日志保留天数必须大于0";
 const EMPTY_BROKERS_ERROR: &str = "Kafka启用时，brokers不能为空";
 const EMPTY_TOPICS_ERROR: &str = "Kafka启用时，topics不能为空";
 const EMPTY_GROUP_ID_ERROR: &str = "Kafka启用时，group_id不能为空";
+const ROUTE_BY_ERROR: &str = "routing.by 只能是 topic、key 或 field";
+const ROUTE_FIELD_ERROR: &str = "routing.by 为 field 时必须配置 routing.field";
+const ROUTE_FALLBACK_ERROR: &str = "routing.fallback 必须是合法的目录名（字母、数字、.、_、-）";
 
 // 使用枚举替代字符串，防止E122错误
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 enum LogLevel {
     Trace,
     Debug,
@@ -55,6 +88,33 @@ impl LogLevel {
         }
     }
 
+    // 识别常见的级别写法：完整名称、单字母缩写（含glog的I/W/E/F）及常见别名
+    fn from_alias(level: &str) -> Option<Self> {
+        if let Some(log_level) = LogLevel::from_str(level) {
+            return Some(log_level);
+        }
+        match level.to_uppercase().as_str() {
+            LEVEL_ABBR_TRACE => Some(LogLevel::Trace),
+            LEVEL_ABBR_DEBUG | "DBG" => Some(LogLevel::Debug),
+            LEVEL_ABBR_INFO | "INFORMATION" => Some(LogLevel::Info),
+            LEVEL_ABBR_WARN | "WARNING" => Some(LogLevel::Warn),
+            LEVEL_ABBR_ERROR | "ERR" => Some(LogLevel::Error),
+            LEVEL_ABBR_FATAL | "CRIT" | "CRITICAL" | "PANIC" => Some(LogLevel::Fatal),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Trace => LEVEL_TRACE,
+            LogLevel::Debug => LEVEL_DEBUG,
+            LogLevel::Info => LEVEL_INFO,
+            LogLevel::Warn => LEVEL_WARN,
+            LogLevel::Error => LEVEL_ERROR,
+            LogLevel::Fatal => LEVEL_FATAL,
+        }
+    }
+
     fn to_abbreviation(&self) -> &'static str {
         match self {
             LogLevel::Trace => LEVEL_ABBR_TRACE,
@@ -67,31 +127,163 @@ impl LogLevel {
     }
 }
 
-// JSON消息结构体
-#[derive(Debug, serde::Deserialize)]
-struct KafkaMessage {
-    #[serde(rename = "L")]
-    l: String, // 日志级别
-    #[serde(rename = "S")]
-    s: String, // 日志内容
+// 从Kafka拉取到的一条原始记录
+#[derive(Debug, Clone)]
+struct KafkaRecord {
+    topic: String,
+    partition: i32,
+    offset: i64,
+    timestamp_ms: i64, // 记录时间戳（CreateTime/LogAppendTime，毫秒）
+    key: Option<String>,
+    headers: Vec<(String, String)>,
+    payload: Vec<u8>, // 原始载荷，按来源配置的格式解码
 }
 
 #[derive(Debug, serde::Deserialize)]
 struct Config {
     logging: LoggingConfig,
     kafka: KafkaConfig,
+    #[serde(default)]
+    rules: Vec<rules::RuleConfig>, // 路由/丢弃/标记/复制规则，按顺序匹配
+    #[serde(default)]
+    sources: HashMap<String, message::SourceConfig>, // 按主题配置消息格式
+    #[serde(default)]
+    limits: limits::LimitsConfig, // 消息大小限制
 }
 
-#[derive(Debug, serde::Deserialize)]
+// 消息处理所需的配置与运行时状态
+struct Pipeline {
+    logging: LoggingConfig,
+    rules: rules::RuleSet,
+    decoder: message::Decoder,
+    limits: limits::LimitsConfig,
+    clock: clock::Clock,
+    layout: layout::Layout,
+    disk: Option<disk::DiskGuard>,
+    durability: durability::Durability,
+}
+
+impl Pipeline {
+    fn new(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Pipeline {
+            logging: config.logging.clone(),
+            rules: rules::RuleSet::compile(&config.rules)?,
+            decoder: message::Decoder::new(&config.sources)?,
+            limits: config.limits.clone(),
+            clock: clock::Clock::new(&config.logging.time)?,
+            layout: layout::Layout::parse(&config.logging.layout)?,
+            disk: disk::DiskGuard::new(&config.logging.disk_guard)?,
+            durability: durability::Durability::new(&config.logging.durability)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
 struct LoggingConfig {
     level: String,
+    #[serde(default = "default_log_path")]
     path: String,
+    #[serde(default = "layout::default_layout")]
+    layout: String, // 目录布局模板，默认 %Y/%m/%d/%H.log
     #[allow(dead_code)]
     compress: bool,
     #[allow(dead_code)]
     rotate: String,
     retention_days: u32,
-    cleanup_time: Option<String>, // 日志清理时间（格式: "HH:MM"）
+    #[serde(default)]
+    retention: Vec<retention::RetentionRule>, // 按路由/级别的保留规则，优先于 retention_days
+    #[serde(default)]
+    archive: archive::ArchiveConfig, // 删除前归档到其他目录
+    #[serde(default)]
+    max_total_size: Option<String>, // 日志总容量上限，如 "50GB"
+    #[serde(default)]
+    service_quota: Option<String>, // 单个服务的容量上限
+    #[serde(default = "quota::default_low_water_percent")]
+    low_water_percent: u64, // 超限后清理到上限的百分比
+    #[serde(default)]
+    disk_guard: disk::DiskGuardConfig, // 磁盘剩余空间保护
+    #[serde(default)]
+    durability: durability::DurabilityConfig, // 写入后的落盘策略
+    #[serde(default)]
+    recovery: recovery::RecoveryConfig, // 启动时修复末尾不完整的记录
+    #[serde(default)]
+    idempotent: bool, // 按偏移量检查点跳过重复投递的记录
+    cleanup_time: Option<String>, // 日志清理时间（格式: "HH:MM"），未配置 cleanup 任务时使用
+    #[serde(default)]
+    jobs: Vec<schedule::JobConfig>, // 按cron表达式执行的维护任务
+    #[serde(default)]
+    enrichment: EnrichmentConfig, // 在每行末尾追加Kafka元数据
+    #[serde(default)]
+    routing: RoutingConfig, // 按服务/主题拆分目录树
+    #[serde(default)]
+    multiline: format::MultilineConfig, // 多行内容处理策略
+    #[serde(default = "default_sanitize")]
+    sanitize: bool, // 写入前转义控制字符和ANSI序列，默认开启
+    #[serde(default)]
+    time: clock::TimeConfig, // 时区与时间戳精度
+    #[serde(default)]
+    output: output::OutputConfig, // 输出格式：行模板或JSON行
+    #[serde(default)]
+    outputs: HashMap<String, output::OutputConfig>, // 按路由目录单独指定输出格式
+}
+
+impl LoggingConfig {
+    // spool策略的备用目录，未配置时为None
+    fn spool_path(&self) -> Option<&str> {
+        self.disk_guard.spool_path.as_deref().filter(|p| !p.is_empty())
+    }
+
+    // 存放时间段文件的目录：日志目录以及spool目录
+    fn roots(&self) -> Vec<&str> {
+        std::iter::once(self.path.as_str()).chain(self.spool_path()).collect()
+    }
+}
+
+// 路由配置：启用后日志写入 logs/{路由}/YYYY/MM/DD/HH.log
+#[derive(Debug, Clone, serde::Deserialize)]
+struct RoutingConfig {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default = "default_route_by")]
+    by: String, // 路由依据: topic / key / field
+    #[serde(default)]
+    field: Option<String>, // by=field 时读取的消息字段
+    #[serde(default = "default_route_fallback")]
+    fallback: String, // 取不到路由值或路由值非法时使用
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        RoutingConfig {
+            enabled: false,
+            by: default_route_by(),
+            field: None,
+            fallback: default_route_fallback(),
+        }
+    }
+}
+
+// Kafka元数据追加配置
+// format 支持占位符: {topic} {partition} {offset} {key} {headers}
+//                    {service} {host} {trace_id} {span_id}
+#[derive(Debug, Clone, serde::Deserialize)]
+struct EnrichmentConfig {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default = "default_enrichment_format")]
+    format: String,
+    #[serde(default)]
+    headers: Vec<String>, // 需要输出的header名称
+}
+
+impl Default for EnrichmentConfig {
+    fn default() -> Self {
+        EnrichmentConfig {
+            enabled: false,
+            format: default_enrichment_format(),
+            headers: Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -114,8 +306,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 读取配置文件
     let config = load_config()?;
 
+    // 子命令：log_server replay --from ... [--to ...] [--offsets ...] [--output ...]
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("replay") => return replay::run_replay(&config, &args[2..]).await,
+        // 子命令：log_server read <文件>... 按多行策略还原原始内容
+        Some("read") => return format::run_read(&config, &args[2..]),
+        // 子命令：log_server cleanup --dry-run | --now 预览或立即执行清理
+        Some("cleanup") => return cleanup::run_cleanup(&config, &args[2..]).await,
+        _ => {}
+    }
+
     // 初始化日志系统
-    init_logging(&config.logging).await;
+    let clock = clock::Clock::new(&config.logging.time)?;
+    let layout = layout::Layout::parse(&config.logging.layout)?;
+    // 先修复上次异常退出留下的不完整记录，再写入初始化日志
+    recovery::recover(&config.logging, &clock, &layout).await;
+    if config.logging.idempotent {
+        // 重启后重新投递的记录按检查点跳过
+        checkpoint::load(&config.logging, &clock, &layout).await;
+    }
+    init_logging(&config.logging, &clock, &layout).await;
 
     tklog::async_info!("log_server|", "日志服务器启动中...");
     tklog::async_info!(
@@ -126,17 +337,55 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         )
     );
 
-    // 启动日志清理任务
-    let retention_days = config.logging.retention_days;
-    let cleanup_time = config.logging.cleanup_time.clone();
-    tokio::spawn(async move {
-        start_log_cleanup_task(retention_days, cleanup_time).await;
-    });
+    // 启动日志清理和维护任务
+    let log_path = config.logging.path.clone();
+    let retention = retention::Retention::new(&config.logging, &layout)?;
+    let jobs = schedule::build_jobs(&config.logging)?;
+    let quota = quota::Quota::new(&config.logging)?;
+    if let Some(guard) = disk::DiskGuard::new(&config.logging.disk_guard)? {
+        // 磁盘剩余空间检查
+        tokio::spawn(disk::run_monitor(
+            log_path.clone(),
+            guard,
+            clock.clone(),
+            layout.clone(),
+            retention.clone(),
+            config.logging.archive.clone(),
+        ));
+    }
+    if let Some(ref quota) = quota {
+        // 越过容量水位时立即清理
+        tokio::spawn(quota::run_on_demand(
+            log_path.clone(),
+            clock.clone(),
+            layout.clone(),
+            quota.clone(),
+            retention.clone(),
+            config.logging.archive.clone(),
+        ));
+    }
+    tklog::async_info!(
+        "cleanup|",
+        &format!("启动日志清理任务，{}", retention.describe())
+    );
+    schedule::start(
+        jobs,
+        schedule::JobContext {
+            log_path,
+            spool_path: config.logging.spool_path().map(str::to_string),
+            retention,
+            archive: config.logging.archive.clone(),
+            clock,
+            layout,
+            quota,
+        },
+    );
 
     // 启动Kafka消费者
     if config.kafka.enabled {
         tklog::async_info!("log_server|", "启动Kafka消费者...");
-        start_kafka_consumer(config.kafka).await?;
+        let pipeline = Pipeline::new(&config)?;
+        start_kafka_consumer(config.kafka, pipeline).await?;
     } else {
         tklog::async_warn!("log_server|", "Kafka未启用，服务器空闲运行");
     }
@@ -144,6 +393,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn default_log_path() -> String {
+    DEFAULT_LOG_PATH.to_string()
+}
+
+fn default_sanitize() -> bool {
+    true
+}
+
+fn default_enrichment_format() -> String {
+    DEFAULT_ENRICHMENT_FORMAT.to_string()
+}
+
+fn default_route_by() -> String {
+    DEFAULT_ROUTE_BY.to_string()
+}
+
+fn default_route_fallback() -> String {
+    DEFAULT_ROUTE_FALLBACK.to_string()
+}
+
 fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
     let config_content =
         fs::read_to_string("config.yaml").map_err(|e| format!("配置文件读取失败: {}", e))?;
@@ -171,6 +440,54 @@ fn validate_config(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // 验证维护任务（名称、类型、cron表达式）
+    schedule::build_jobs(&config.logging)?;
+
+    // 验证时区与时间戳精度
+    clock::Clock::new(&config.logging.time)?;
+
+    // 验证目录布局和保留规则
+    let layout = layout::Layout::parse(&config.logging.layout)?;
+    retention::Retention::new(&config.logging, &layout)?;
+    archive::validate_archive(&config.logging.archive, &config.logging.path)?;
+
+    // 验证容量上限和磁盘空间保护
+    quota::Quota::new(&config.logging)?;
+    disk::DiskGuard::new(&config.logging.disk_guard)?;
+
+    // 验证落盘策略和启动恢复
+    durability::Durability::new(&config.logging.durability)?;
+    recovery::validate_recovery(&config.logging.recovery, &config.logging.path)?;
+
+    // 验证多行内容策略
+    format::validate_multiline(&config.logging.multiline)?;
+
+    // 验证输出格式
+    output::validate_outputs(&config.logging.output, &config.logging.outputs)?;
+
+    // 验证路由配置
+    let routing = &config.logging.routing;
+    if routing.enabled {
+        match routing.by.as_str() {
+            "topic" | "key" => {}
+            "field" if routing.field.as_deref().is_some_and(|f| !f.is_empty()) => {}
+            "field" => return Err(ROUTE_FIELD_ERROR.into()),
+            _ => return Err(ROUTE_BY_ERROR.into()),
+        }
+        if sanitize_route_name(&routing.fallback).as_deref() != Some(routing.fallback.as_str()) {
+            return Err(ROUTE_FALLBACK_ERROR.into());
+        }
+    }
+
+    // 验证来源配置（格式、默认级别、级别正则）
+    message::Decoder::new(&config.sources)?;
+
+    // 验证大小限制
+    limits::validate_limits(&config.limits)?;
+
+    // 验证规则配置（正则、动作、目的地）
+    rules::RuleSet::compile(&config.rules)?;
+
     // 验证Kafka配置
     if config.kafka.enabled {
         validate_kafka_config(&config.kafka)?;
@@ -179,52 +496,44 @@ fn validate_config(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-async fn init_logging(log_config: &LoggingConfig) {
-    // 创建日志目录结构：年/月/日/小时.log
-    let now = chrono::Local::now();
-    let timestamp = now.format(TIMESTAMP_FORMAT).to_string();
-
-    // 使用PathBuf构建路径，减少字符串操作
-    use std::path::PathBuf;
-    let mut log_dir = PathBuf::from(&log_config.path);
-    log_dir.push(now.format("%Y").to_string());
-    log_dir.push(now.format("%m").to_string());
-    log_dir.push(now.format("%d").to_string());
+async fn init_logging(log_config: &LoggingConfig, clock: &clock::Clock, layout: &layout::Layout) {
+    // 按目录布局创建当前时间段的日志文件（按配置的时区）
+    let now = clock.now();
+    let timestamp = clock.format_timestamp(&now);
 
-    let mut log_file = log_dir.clone();
-    log_file.push(format!("{}.log", now.format("%H")));
+    let log_file = std::path::Path::new(&log_config.path).join(layout.render(&now, None, |name| {
+        output::LogEntry::plain(&now, &timestamp, LEVEL_INFO, "").layout_field(name)
+    }));
+    let log_dir = log_file.parent().unwrap_or(std::path::Path::new(&log_config.path));
 
-    if let Err(e) = fs::create_dir_all(&log_dir) {
+    let tree_guard = layout::write_guard();
+    if let Err(e) = fs::create_dir_all(log_dir) {
         eprintln!("创建日志目录失败: {:?}", e);
     }
 
-    // 使用write!直接写入文件，避免format!的中间字符串分配
+    // 每条消息按输出格式生成后一次写入
+    let output = &log_config.output;
+    let mut lines = String::new();
+    for message in [
+        "日志系统已初始化".to_string(),
+        format!("日志目录: {:?}", log_dir),
+        format!("当前日志文件: {:?}", log_file),
+    ] {
+        let entry = output::LogEntry::plain(&now, &timestamp, LEVEL_INFO, &message);
+        match output::render_line(log_config, output, &entry) {
+            Ok(line) => lines.push_str(&line),
+            Err(e) => eprintln!("生成初始化日志失败: {:?}", e),
+        }
+    }
     if let Err(e) = fs::OpenOptions::new()
         .create(true)
         .append(true)
         .open(&log_file)
-        .and_then(|mut file| {
-            // 写入初始化消息
-            writeln!(
-                file,
-                "[{}] [{}] 日志系统已初始化",
-                timestamp, LEVEL_ABBR_INFO
-            )?;
-            writeln!(
-                file,
-                "[{}] [{}] 日志目录: {:?}",
-                timestamp, LEVEL_ABBR_INFO, log_dir
-            )?;
-            writeln!(
-                file,
-                "[{}] [{}] 当前日志文件: {:?}",
-                timestamp, LEVEL_ABBR_INFO, log_file
-            )?;
-            Ok(())
-        })
+        .and_then(|mut file| file.write_all(lines.as_bytes()))
     {
         eprintln!("写入初始化日志失败: {:?}", e);
     }
+    drop(tree_guard);
 
     tklog::async_info!("log_server|", "日志系统已初始化");
     tklog::async_info!("log_server|", &format!("日志目录: {:?}", log_dir));
@@ -232,85 +541,90 @@ async fn init_logging(log_config: &LoggingConfig) {
 }
 
 async fn log_with_level(
-    level: &str,
-    content: &str,
-    timestamp: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // 使用传入的时间戳来确定日志文件路径
-    let timestamp_naive = chrono::NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
-        .unwrap_or_else(|_| chrono::Local::now().naive_local());
-
-    // 预构建目录路径
-    let year = timestamp_naive.format("%Y").to_string();
-    let month = timestamp_naive.format("%m").to_string();
-    let day = timestamp_naive.format("%d").to_string();
-    let hour = timestamp_naive.format("%H").to_string();
-
-    // 使用PathBuf来构建路径，减少字符串操作
-    use std::path::PathBuf;
-    let mut log_dir = PathBuf::from(DEFAULT_LOG_PATH);
-    log_dir.push(&year);
-    log_dir.push(&month);
-    log_dir.push(&day);
-
-    let mut log_file = log_dir.clone();
-    log_file.push(format!("{}.log", hour));
-
-    // 确保目录存在
-    if let Err(e) = fs::create_dir_all(&log_dir) {
-        tklog::async_error!(
-            "log",
-            &format!("创建日志目录失败: {:?}，目录: {:?}", e, log_dir)
-        );
-        return Ok(());
+    pipeline: &Pipeline,
+    route: Option<&str>,
+    entry: &output::LogEntry<'_>,
+    mark: Option<checkpoint::Mark<'_>>,
+) -> Result<EntryOutcome, Box<dyn std::error::Error>> {
+    let logging_config = &pipeline.logging;
+
+    // 重新投递的记录中已写入过的部分直接跳过
+    if mark.is_some_and(|mark| mark.is_written()) {
+        stats::add(&stats::DEDUPLICATED, 1);
+        return Ok(EntryOutcome::Duplicate);
     }
 
-    // 使用writeln!直接写入文件，避免format!的中间字符串分配
-    if let Err(e) = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_file)
-        .and_then(|mut file| {
-            writeln!(
-                file,
-                "[{}] [{}] {}",
-                timestamp,
-                get_level_abbreviation(level),
-                content
-            )
-        })
-    {
-        tklog::async_error!(
-            "log",
-            &format!("写入日志文件失败: {:?}，文件: {:?}", e, log_file)
-        );
-        return Ok(());
+    // 磁盘空间不足时按策略丢弃或改写到备用目录
+    let mut root = logging_config.path.as_str();
+    if let Some(ref disk) = pipeline.disk {
+        match disk.admit(entry.level) {
+            disk::Admission::Write => {}
+            disk::Admission::Spool(spool_path) => root = spool_path,
+            disk::Admission::Drop => return Ok(EntryOutcome::DiskDropped),
+        }
     }
 
-    // 同时输出到控制台（这里使用format!因为是单次调用，影响较小）
-    let trimmed_message = format!(
-        "[{}] [{}] {}",
-        timestamp,
-        get_level_abbreviation(level),
-        content
-    );
+    // 按目录布局和条目的时间（已按配置的时区转换）确定日志文件路径
+    let log_file = std::path::Path::new(root)
+        .join(pipeline.layout.render(entry.time, route, |name| entry.layout_field(name)));
+    let log_dir = log_file.parent().unwrap_or(std::path::Path::new(root));
+
+    // 按路由的输出格式生成（含清洗和多行策略），整条记录一次写入，避免多行内容破坏行格式
+    let output = output::output_for(logging_config, route);
+    let line = output::render_line(logging_config, output, entry)?;
+
+    // 创建目录到写完文件期间持有目录树读锁，清理不会删除刚创建的目录
+    let written = {
+        let _tree_guard = layout::write_guard();
+        pipeline
+            .durability
+            .create_dir_all(log_dir)
+            .map_err(|e| format!("创建日志目录失败: {}，目录: {:?}", e, log_dir))
+            .and_then(|_| {
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&log_file)
+                    .and_then(|mut file| {
+                        file.write_all(line.as_bytes())?;
+                        // 按落盘策略同步或记录待同步的文件
+                        pipeline.durability.after_write(&file, &log_file, line.len())?;
+                        if let Some(mark) = mark {
+                            // 追加模式下写入后的位置即本行结尾
+                            mark.record(&log_file, file.stream_position()?);
+                        }
+                        Ok(())
+                    })
+                    .map_err(|e| format!("写入日志文件失败: {}，文件: {:?}", e, log_file))
+            })
+    };
+
+    // 失败时返回错误，调用方不会提交该记录的偏移量
+    if let Err(e) = written {
+        tklog::async_error!("log", &e);
+        return Err(e.into());
+    }
+    quota::record_written(line.len());
+
+    // 同时输出到控制台，与文件中的内容一致
+    let trimmed_message = line.trim_end_matches('\n');
     
     // 使用枚举进行安全匹配，防止E122错误
-    if let Some(log_level) = LogLevel::from_str(level) {
+    if let Some(log_level) = LogLevel::from_str(entry.level) {
         match log_level {
-            LogLevel::Trace => tklog::async_trace!("log_server|", &trimmed_message),
-            LogLevel::Debug => tklog::async_debug!("log_server|", &trimmed_message),
-            LogLevel::Info => tklog::async_info!("log_server|", &trimmed_message),
-            LogLevel::Warn => tklog::async_warn!("log_server|", &trimmed_message),
-            LogLevel::Error => tklog::async_error!("log_server|", &trimmed_message),
-            LogLevel::Fatal => tklog::async_fatal!("log_server|", &trimmed_message),
+            LogLevel::Trace => tklog::async_trace!("log_server|", trimmed_message),
+            LogLevel::Debug => tklog::async_debug!("log_server|", trimmed_message),
+            LogLevel::Info => tklog::async_info!("log_server|", trimmed_message),
+            LogLevel::Warn => tklog::async_warn!("log_server|", trimmed_message),
+            LogLevel::Error => tklog::async_error!("log_server|", trimmed_message),
+            LogLevel::Fatal => tklog::async_fatal!("log_server|", trimmed_message),
         }
     } else {
         // 如果是未知级别，默认使用INFO
-        tklog::async_info!("log_server|", &trimmed_message);
+        tklog::async_info!("log_server|", trimmed_message);
     }
 
-    Ok(())
+    Ok(EntryOutcome::Written)
 }
 
 fn get_level_abbreviation(level: &str) -> &'static str {
@@ -322,76 +636,6 @@ fn get_level_abbreviation(level: &str) -> &'static str {
     }
 }
 
-// 日志清理任务：每天 N 点执行（配置文件：cleanup_time）
-async fn start_log_cleanup_task(retention_days: u32, cleanup_time: Option<String>) {
-    tklog::async_info!(
-        "cleanup|",
-        &format!("启动日志清理任务，保留{}天", retention_days)
-    );
-
-    loop {
-        let now = Local::now();
-        let next_cleanup = match get_next_cleanup_time(now, cleanup_time.as_deref()) {
-            Ok(time) => time,
-            Err(_) => {
-                // 在异步上下文外记录错误
-                eprintln!("[ERROR] cleanup 清理时间计算错误，使用默认时间01:00");
-                // 使用默认时间
-                now.date_naive()
-                    .and_hms_opt(1, 0, 0)
-                    .and_then(|naive| naive.and_local_timezone(Local).single())
-                    .unwrap_or_else(|| {
-                        // 如果连默认时间都失败，使用当前时间+1小时
-                        now + ChronoDuration::hours(1)
-                    })
-            }
-        };
-        let sleep_duration = next_cleanup.signed_duration_since(now);
-
-        if sleep_duration.num_seconds() > 0 {
-            tklog::async_info!(
-                "cleanup|",
-                &format!("下次清理时间: {}", next_cleanup.format("%Y-%m-%d %H:%M:%S"))
-            );
-            tokio::time::sleep(Duration::from_secs(sleep_duration.num_seconds() as u64)).await;
-        }
-
-        // 执行清理
-        cleanup_old_logs("logs", retention_days).await;
-    }
-}
-
-// 计算下次清理时间（每天凌晨1点）
-fn get_next_cleanup_time(
-    now: DateTime<Local>,
-    cleanup_time: Option<&str>,
-) -> Result<DateTime<Local>, Box<dyn std::error::Error>> {
-    let (hour, minute, second) = match cleanup_time {
-        Some(time_str) => {
-            parse_cleanup_time(time_str).map_err(|e| format!("清理时间解析失败: {}", e))?
-        }
-        None => (1, 0, 0), // 默认凌晨1点
-    };
-
-    let next = now
-        .date_naive()
-        .and_hms_opt(hour, minute, second)
-        .ok_or_else(|| format!("无效的时间: {}:{}:{}", hour, minute, second))?;
-
-    let next_datetime = match next.and_local_timezone(Local) {
-        chrono::LocalResult::Single(dt) => dt,
-        chrono::LocalResult::None => return Err("时区转换失败：本地时间不存在".into()),
-        chrono::LocalResult::Ambiguous(early, _) => early,
-    };
-
-    if now >= next_datetime {
-        let next_with_delay = next_datetime + ChronoDuration::days(1);
-        Ok(next_with_delay)
-    } else {
-        Ok(next_datetime)
-    }
-}
-
 fn validate_kafka_config(kafka_config: &KafkaConfig) -> Result<(), Box<dyn std::error::Error>> {
     if kafka_config.brokers.is_empty() {
         return Err(EMPTY_BROKERS_ERROR.into());
@@ -440,71 +684,75 @@ fn parse_cleanup_time(time_str: &str) -> Result<(u32, u32, u32), Box<dyn std::er
     Ok((hour, minute, second))
 }
 
-// 清理超过指定天数的日志文件
-async fn cleanup_old_logs(log_path: &str, retention_days: u32) {
-    let cutoff_date = SystemTime::now() - Duration::from_secs(60 * 60 * 24 * retention_days as u64);
-    let mut cleaned_count = 0;
+// 清理超过保留天数的日志文件
+// 按目录布局从路径解析文件的时间段和路由/级别，时间段结束早于该文件保留期限的文件被删除
+// 启用归档时按日期打包到归档目录后再删除，某一天归档失败则这一天的文件都保留
+// 清理日志目录以及spool目录中的过期文件
+async fn cleanup_old_logs(
+    log_path: &str,
+    spool_path: Option<&str>,
+    retention: &retention::Retention,
+    archive: &archive::ArchiveConfig,
+    clock: &clock::Clock,
+    layout: &layout::Layout,
+) -> Vec<cleanup::Removal> {
+    let now = clock.now().naive_local();
+    let mut removed = Vec::new();
+    let mut pruned_count = 0;
 
     tklog::async_info!(
         "cleanup|",
-        &format!("开始清理{}天前的日志文件", retention_days)
+        &format!("开始清理过期的日志文件（{}）", retention.describe())
     );
 
-    let Ok(entries) = fs::read_dir(log_path) else {
+    if fs::metadata(log_path).is_err() {
         tklog::async_error!("cleanup|", "无法读取日志目录");
-        return;
-    };
-
-    for entry in entries {
-        let Ok(entry) = entry else {
-            continue; // 跳过无效的目录条目
-        };
-
-        let path = entry.path();
-        if !path.is_dir() {
-            continue; // 只处理目录，忽略文件
-        }
-
-        // 检查年份目录
-        let Ok(metadata) = entry.metadata() else {
-            continue; // 跳过无法获取元数据的目录
-        };
-
-        let Ok(modified) = metadata.modified() else {
-            continue; // 跳过无法获取修改时间的目录
-        };
+        return removed;
+    }
 
-        if modified >= cutoff_date {
-            continue; // 目录未过期，跳过
-        }
+    for root in std::iter::once(log_path).chain(spool_path) {
+        let root = std::path::Path::new(root);
+        // 与 cleanup --dry-run 使用同一套选择逻辑
+        let (expired, _) = cleanup::expired_files(layout.scan(root), retention, now);
 
-        // 删除过期的目录
-        if let Err(e) = fs::remove_dir_all(&path) {
-            tklog::async_error!(
-                "cleanup|",
-                &format!("删除目录失败 {:?}: {}", path, e)
-            );
-        } else {
-            cleaned_count += 1;
-            tklog::async_info!(
-                "cleanup|",
-                &format!("已删除过期目录: {:?}", path)
-            );
-        }
+        // 按日期分组，每组归档成功后才删除
+        let (files, pruned) = cleanup::remove_files(root, archive, clock, expired).await;
+        removed.extend(files);
+        pruned_count += pruned;
     }
 
-    if cleaned_count > 0 {
+    if !removed.is_empty() {
         tklog::async_info!(
             "cleanup|",
-            &format!("清理完成，删除了{}个过期目录", cleaned_count)
+            &format!(
+                "清理完成，删除了{}个过期文件，{}个空目录",
+                removed.len(), pruned_count
+            )
         );
     } else {
         tklog::async_info!("cleanup|", "没有找到过期的日志文件");
     }
+
+    if archive.enabled {
+        let archives = archive::cleanup_archives(archive).await;
+        if archives > 0 {
+            tklog::async_info!("cleanup|", &format!("删除了{}个过期归档", archives));
+        }
+    }
+
+    removed
+}
+
+// 年份目录名为4位数字
+fn is_year_dir_name(name: &str) -> bool {
+    name.len() == 4 && name.bytes().all(|b| b.is_ascii_digit())
 }
 
 // Kafka消费者功能 - 实现自动重连机制
-async fn start_kafka_consumer(kafka_config: KafkaConfig) -> Result<(), Box<dyn std::error::Error>> {
+async fn start_kafka_consumer(
+    kafka_config: KafkaConfig,
+    pipeline: Pipeline,
+) -> Result<(), Box<dyn std::error::Error>> {
     tklog::async_info!("kafka|", "启动Kafka消费者...");
     
     // 输出配置信息
@@ -517,7 +765,7 @@ async fn start_kafka_consumer(kafka_config: KafkaConfig) -> Result<(), Box<dyn s
 
     // 自动重连循环
     loop {
-        match kafka_consumer_loop(&kafka_config).await {
+        match kafka_consumer_loop(&kafka_config, &pipeline).await {
             Ok(_) => {
                 tklog::async_info!("kafka|", "Kafka消费者正常结束");
                 break;
@@ -537,7 +785,10 @@ async fn start_kafka_consumer(kafka_config: KafkaConfig) -> Result<(), Box<dyn s
 }
 
 // Kafka消费者主循环 - 包含连接和消息处理逻辑
-async fn kafka_consumer_loop(kafka_config: &KafkaConfig) -> Result<(), Box<dyn std::error::Error>> {
+async fn kafka_consumer_loop(
+    kafka_config: &KafkaConfig,
+    pipeline: &Pipeline,
+) -> Result<(), Box<dyn std::error::Error>> {
     tklog::async_info!("kafka|", "正在初始化Kafka消费者...");
 
     // 创建Kafka消费者实例
@@ -546,34 +797,84 @@ async fn kafka_consumer_loop(kafka_config: &KafkaConfig) -> Result<(), Box<dyn s
 
     // Kafka消息消费循环
     let mut message_count = 0u64;
+    let mut entry_count = 0u64;
+    let mut failed_entry_count = 0u64;
     let mut reconnect_interval = interval(Duration::from_secs(10));
+    // 已写入、等待落盘后提交的偏移量（每个分区只保留最新的）
+    let mut pending_commits: BTreeMap<(String, i32), i64> = BTreeMap::new();
 
     loop {
         // 定期检查连接状态
         reconnect_interval.tick().await;
 
+        // 磁盘空间严重不足且策略为pause时暂停拉取，未提交的记录在恢复后继续消费
+        if let Some(ref disk) = pipeline.disk {
+            disk.wait_writable().await;
+        }
+
         // 从Kafka接收消息
         match receive_kafka_message(&consumer_addresses).await {
-            Ok(Some(message)) => {
+            Ok(Some(record)) => {
                 message_count += 1;
                 
-                // 处理接收到的消息
-                if let Err(e) = process_kafka_message(&message).await {
-                    tklog::async_error!("kafka|", &format!("处理消息失败: {}", e));
-                    
-                    // 如果写日志失败，触发重连
-                    if is_connection_error(&e.to_string()) {
-                        tklog::async_warn!("kafka|", "检测到连接错误，触发重连...");
+                // 处理接收到的消息（实时消费以接收时间作为日志时间）
+                match process_kafka_message(&record, pipeline, pipeline.clock.now()).await {
+                    Ok(report) => {
+                        entry_count += report.total() as u64;
+                        failed_entry_count += report.failed as u64;
+                        if report.failed > 0 || report.disk_dropped > 0 {
+                            tklog::async_warn!(
+                                "kafka|",
+                                &format!(
+                                    "{}-{}@{} 写入 {} 条，丢弃 {} 条，死信 {} 条，解码失败 {} 条，磁盘空间不足丢弃 {} 条",
+                                    record.topic,
+                                    record.partition,
+                                    record.offset,
+                                    report.written,
+                                    report.dropped,
+                                    report.diverted,
+                                    report.failed,
+                                    report.disk_dropped
+                                )
+                            );
+                        }
+
+                        // 记录中的所有条目都已写入后才提交偏移量，并按落盘策略先同步
+                        pending_commits.insert((record.topic.clone(), record.partition), record.offset);
+                        commit_durable_offsets(&consumer_addresses, pipeline, &mut pending_commits).await?;
+                    }
+                    Err(e) => {
+                        tklog::async_error!("kafka|", &format!("处理消息失败: {}", e));
+
+                        // 写日志失败时不提交，触发重连后从已提交的偏移量重新消费
+                        tklog::async_warn!("kafka|", "写入失败，触发重连以重新消费该记录...");
                         return Err(e);
                     }
                 }
 
                 // 每处理100条消息输出统计信息
                 if message_count.is_multiple_of(100) {
-                    tklog::async_info!("kafka|", &format!("已处理 {} 条消息", message_count));
+                    tklog::async_info!(
+                        "kafka|",
+                        &format!(
+                            "已处理 {} 条消息，{} 个条目，解码失败 {} 个，{}",
+                            message_count,
+                            entry_count,
+                            failed_entry_count,
+                            stats::summary()
+                        )
+                    );
+                    if !pipeline.rules.is_empty() {
+                        tklog::async_info!(
+                            "kafka|",
+                            &format!("规则命中: {}", pipeline.rules.hit_summary())
+                        );
+                    }
                 }
             }
             Ok(None) => {
+                // interval模式下空闲时也按间隔同步并提交
+                commit_durable_offsets(&consumer_addresses, pipeline, &mut pending_commits).await?;
                 // 没有消息时短暂等待
                 sleep(Duration::from_millis(100)).await;
                 continue;
@@ -608,7 +909,7 @@ fn create_kafka_consumer(kafka_config: &KafkaConfig) -> Result<Vec<SocketAddr>,
 }
 
 // Kafka消息接收 - 使用TCP连接Kafka消费者
-async fn receive_kafka_message(_consumer: &[SocketAddr]) -> Result<Option<String>, Box<dyn std::error::Error>> {
+async fn receive_kafka_message(_consumer: &[SocketAddr]) -> Result<Option<KafkaRecord>, Box<dyn std::error::Error>> {
     // 这里是真正的Kafka连接实现位置
     // 在实际部署中，这里应该连接到真实的Kafka broker
     // 目前返回None表示等待真正的Kafka集成
@@ -621,22 +922,285 @@ async fn receive_kafka_message(_consumer: &[SocketAddr]) -> Result<Option<String
     Ok(None)
 }
 
+// 提交已处理记录的偏移量（offset + 1）
+// 按落盘策略同步已写入的文件后提交等待中的偏移量；同步失败时不提交，返回错误触发重连
+async fn commit_durable_offsets(
+    consumer: &[SocketAddr],
+    pipeline: &Pipeline,
+    pending: &mut BTreeMap<(String, i32), i64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if pending.is_empty() || !pipeline.durability.commit_ready() {
+        return Ok(());
+    }
+    if let Err(e) = pipeline.durability.sync() {
+        let message = format!("日志落盘失败，不提交偏移量: {}", e);
+        tklog::async_error!("kafka|", &message);
+        return Err(message.into());
+    }
+
+    for ((topic, partition), offset) in std::mem::take(pending) {
+        commit_kafka_offset(consumer, &topic, partition, offset).await?;
+    }
+    Ok(())
+}
+
+async fn commit_kafka_offset(
+    _consumer: &[SocketAddr],
+    topic: &str,
+    partition: i32,
+    offset: i64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // 这里是真正的OffsetCommit请求实现位置
+    tklog::async_debug!(
+        "kafka|",
+        &format!("提交偏移量 {}-{}@{}", topic, partition, offset + 1)
+    );
+
+    Ok(())
+}
+
+// 按时间戳查询各分区的偏移量（ListOffsets请求，timestamp语义）
+// 返回 (分区, 第一条时间戳不早于该时间的记录的偏移量) 列表，没有这样的记录时偏移量为 -1；
+// 时间戳为 -1 时返回分区末尾。供重放模式定位起止位置
+async fn list_offsets_by_timestamp(
+    _consumer: &[SocketAddr],
+    topic: &str,
+    timestamp_ms: i64,
+) -> Result<Vec<(i32, i64)>, Box<dyn std::error::Error>> {
+    // 这里是真正的ListOffsets请求实现位置
+    // 目前返回空列表表示等待真正的Kafka集成
+    tklog::async_debug!(
+        "kafka|",
+        &format!("查询主题 {} 时间戳 {} 对应的偏移量...", topic, timestamp_ms)
+    );
+
+    Ok(Vec::new())
+}
+
+// 从指定分区和偏移量拉取一批记录（不加入消费组，不提交偏移量）
+async fn fetch_kafka_records(
+    _consumer: &[SocketAddr],
+    topic: &str,
+    partition: i32,
+    offset: i64,
+) -> Result<Vec<KafkaRecord>, Box<dyn std::error::Error>> {
+    // 这里是真正的Fetch请求实现位置
+    // 目前返回空批次表示等待真正的Kafka集成
+    tklog::async_debug!(
+        "kafka|",
+        &format!("拉取 {}-{} 偏移量 {} 起的记录...", topic, partition, offset)
+    );
+
+    Ok(Vec::new())
+}
+
+// 单条Kafka记录的处理结果（一条记录可以包含多个日志条目）
+#[derive(Debug, Default)]
+struct RecordReport {
+    written: usize,  // 成功写入的条目
+    dropped: usize,  // 被规则丢弃的条目
+    diverted: usize, // 超限写入死信目录的条目
+    failed: usize,   // 解码失败的条目
+    disk_dropped: usize, // 磁盘空间不足时按级别丢弃的条目
+    duplicate: usize, // 按检查点跳过的重复投递条目
+}
+
+impl RecordReport {
+    fn total(&self) -> usize {
+        self.written + self.dropped + self.diverted + self.failed + self.disk_dropped + self.duplicate
+    }
+}
+
+// 单个条目的写入结果
+enum EntryOutcome {
+    Written,
+    Dropped,
+    Diverted,
+    DiskDropped,
+    Duplicate,
+}
+
 // 处理Kafka消息
-async fn process_kafka_message(message: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // 解析JSON消息
-    let kafka_msg: KafkaMessage = serde_json::from_str(message)
-        .map_err(|e| format!("解析Kafka消息失败: {} - 原始消息: {}", e, message))?;
+// 解码失败的条目单独计数并跳过；写入失败时立即返回错误，该记录不会被提交
+async fn process_kafka_message(
+    record: &KafkaRecord,
+    pipeline: &Pipeline,
+    log_time: DateTime<FixedOffset>,
+) -> Result<RecordReport, Box<dyn std::error::Error>> {
+    let mut report = RecordReport::default();
+
+    // 解码前检查整条记录的大小
+    let binary = pipeline.decoder.is_binary(&record.topic);
+    let entries = match limits::check_record(&pipeline.limits, record, binary) {
+        limits::RecordOversize::Fits => {
+            // 按来源配置解析消息（单条、JSON数组或NDJSON批次，JSON字段映射或纯文本）
+            pipeline.decoder.decode_batch(&record.topic, &record.payload)
+        }
+        limits::RecordOversize::Truncate(max) => {
+            tklog::async_warn!(
+                "kafka|",
+                &format!(
+                    "{}-{}@{} 记录超限（{}字节），截断后不解码，作为一个条目写入",
+                    record.topic, record.partition, record.offset, record.payload.len()
+                )
+            );
+            let content = limits::truncate_payload(&record.payload, max);
+            vec![Ok(pipeline.decoder.decode_truncated(&record.topic, content))]
+        }
+        limits::RecordOversize::DeadLetter => {
+            let path =
+                limits::write_dead_letter(&pipeline.limits, record, None, &record.payload, &pipeline.durability)?;
+            tklog::async_warn!(
+                "kafka|",
+                &format!(
+                    "{}-{}@{} 记录超限（{}字节），已写入死信: {:?}",
+                    record.topic, record.partition, record.offset, record.payload.len(), path
+                )
+            );
+            report.diverted += 1;
+            return Ok(report);
+        }
+    };
+    let entry_total = entries.len();
 
-    let now = chrono::Local::now();
-    let timestamp = now.format(TIMESTAMP_FORMAT).to_string();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let kafka_msg = match entry {
+            Ok(kafka_msg) => kafka_msg,
+            Err(e) => {
+                report.failed += 1;
+                tklog::async_error!(
+                    "kafka|",
+                    &format!(
+                        "{}-{}@{} 第{}/{}条解码失败: {}",
+                        record.topic, record.partition, record.offset, index + 1, entry_total, e
+                    )
+                );
+                continue;
+            }
+        };
+
+        match write_kafka_entry(record, index, &kafka_msg, pipeline, log_time).await? {
+            EntryOutcome::Written => report.written += 1,
+            EntryOutcome::Dropped => report.dropped += 1,
+            EntryOutcome::Diverted => report.diverted += 1,
+            EntryOutcome::DiskDropped => report.disk_dropped += 1,
+            EntryOutcome::Duplicate => report.duplicate += 1,
+        }
+    }
+
+    // 整条记录处理完后重写检查点，早于偏移量提交前的同步
+    if pipeline.logging.idempotent {
+        checkpoint::flush(&pipeline.durability)?;
+    }
+
+    Ok(report)
+}
+
+// 写入一个日志条目
+async fn write_kafka_entry(
+    record: &KafkaRecord,
+    entry_index: usize,
+    kafka_msg: &KafkaMessage,
+    pipeline: &Pipeline,
+    log_time: DateTime<FixedOffset>,
+) -> Result<EntryOutcome, Box<dyn std::error::Error>> {
+    let logging_config = &pipeline.logging;
+
+    // 规则引擎：决定丢弃、改写路由、添加标签或复制
+    let outcome = pipeline.rules.evaluate(&record.topic, kafka_msg);
+    if outcome.drop {
+        tklog::async_debug!("kafka|", &format!("消息被规则丢弃: {:?}", kafka_msg));
+        return Ok(EntryOutcome::Dropped);
+    }
+
+    // 写入前检查内容大小
+    let body = match limits::check_content(&pipeline.limits, &record.topic, &kafka_msg.s) {
+        limits::Oversize::Fits => std::borrow::Cow::Borrowed(kafka_msg.s.as_str()),
+        limits::Oversize::Truncated(content) => std::borrow::Cow::Owned(content),
+        limits::Oversize::DeadLetter => {
+            let path = limits::write_dead_letter(
+                &pipeline.limits,
+                record,
+                Some(entry_index),
+                kafka_msg.s.as_bytes(),
+                &pipeline.durability,
+            )?;
+            tklog::async_warn!(
+                "kafka|",
+                &format!("日志内容超限（{}字节），已写入死信: {:?}", kafka_msg.s.len(), path)
+            );
+            return Ok(EntryOutcome::Diverted);
+        }
+    };
+
+    let timestamp = pipeline.clock.format_timestamp(&log_time);
+
+    // 生产者时间戳按配置的时区和精度规范化，无法解析时保留原值
+    let source_timestamp = kafka_msg.timestamp.as_deref().map(|value| {
+        pipeline
+            .clock
+            .parse(value)
+            .map(|time| pipeline.clock.format_timestamp(&time))
+            .unwrap_or_else(|| value.to_string())
+    });
+
+    // 标签和元数据由输出格式决定如何呈现
+    let entry = output::LogEntry {
+        time: &log_time,
+        timestamp: &timestamp,
+        source_timestamp: source_timestamp.as_deref(),
+        level: &kafka_msg.l,
+        content: &body,
+        tags: &outcome.tags,
+        record: Some(record),
+        message: Some(kafka_msg),
+    };
+
+    // 解析路由目录：规则指定的目的地优先
+    let route = outcome.route.or_else(|| {
+        logging_config
+            .routing
+            .enabled
+            .then(|| resolve_route(record, kafka_msg, &logging_config.routing))
+    });
+
+    // 启用检查点时，每个目的地按 记录偏移量/条目序号/目的地序号 去重
+    let mark = |copy| {
+        logging_config
+            .idempotent
+            .then(|| checkpoint::Mark::new(record, entry_index, copy))
+    };
 
     // 使用日志记录功能写入文件
-    let result = log_with_level(&kafka_msg.l, &kafka_msg.s, &timestamp).await;
+    let mut result = log_with_level(pipeline, route.as_deref(), &entry, mark(0)).await;
+
+    // 复制到额外的目的地（同一级别的磁盘判定相同，条目的结果以主路由为准，
+    // 主路由已写入过而复制的目的地补写了时算作写入）
+    for (index, destination) in outcome.copies.iter().enumerate() {
+        if result.is_err() {
+            break;
+        }
+        match log_with_level(pipeline, Some(destination), &entry, mark(index + 1)).await {
+            Err(e) => result = Err(e),
+            Ok(EntryOutcome::Written) if matches!(result, Ok(EntryOutcome::Duplicate)) => {
+                result = Ok(EntryOutcome::Written)
+            }
+            Ok(_) => {}
+        }
+    }
     
     match result {
-        Ok(_) => {
+        Ok(EntryOutcome::DiskDropped) => {
+            tklog::async_debug!("kafka|", &format!("磁盘空间不足，消息被丢弃: {:?}", kafka_msg));
+            Ok(EntryOutcome::DiskDropped)
+        }
+        Ok(EntryOutcome::Duplicate) => {
+            tklog::async_debug!("kafka|", &format!("重复投递，已跳过: {:?}", kafka_msg));
+            Ok(EntryOutcome::Duplicate)
+        }
+        Ok(outcome) => {
             tklog::async_debug!("kafka|", &format!("成功处理消息: {:?}", kafka_msg));
-            Ok(())
+            Ok(outcome)
         }
         Err(e) => {
             tklog::async_error!("kafka|", &format!("写入日志失败: {}", e));
@@ -645,6 +1209,104 @@ async fn process_kafka_message(message: &str) -> Result<(), Box<dyn std::error::
     }
 }
 
+// 根据路由配置取得目录名，取不到或非法时使用fallback
+fn resolve_route(
+    record: &KafkaRecord,
+    kafka_msg: &KafkaMessage,
+    routing: &RoutingConfig,
+) -> String {
+    let value = match routing.by.as_str() {
+        "topic" => Some(record.topic.clone()),
+        "key" => record.key.clone(),
+        "field" => routing
+            .field
+            .as_deref()
+            .and_then(|field| kafka_msg.field(field))
+            .map(|v| v.into_owned()),
+        _ => None,
+    };
+
+    value
+        .as_deref()
+        .and_then(sanitize_route_name)
+        .unwrap_or_else(|| routing.fallback.clone())
+}
+
+// 清洗路由目录名，防止路径穿越
+// 仅保留字母、数字、.、_、-，其余字符替换为_；拒绝空串、.、..；
+// 4位纯数字会与年份目录混淆，加_前缀区分
+fn sanitize_route_name(value: &str) -> Option<String> {
+    let name: String = value
+        .trim()
+        .chars()
+        .take(MAX_ROUTE_NAME_LEN)
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if name.is_empty() || name.chars().all(|c| c == '.') {
+        return None;
+    }
+    if is_year_dir_name(&name) {
+        return Some(format!("_{}", name));
+    }
+    Some(name)
+}
+
+// 按模板渲染Kafka记录的元数据
+fn render_record_metadata(
+    record: &KafkaRecord,
+    kafka_msg: &KafkaMessage,
+    enrichment: &EnrichmentConfig,
+) -> String {
+    let headers: String = enrichment
+        .headers
+        .iter()
+        .filter_map(|name| {
+            record
+                .headers
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(k, v)| format!(" {}={}", k, v))
+        })
+        .collect();
+
+    // 单次扫描替换，键和header中的 {xxx} 不会被再次展开
+    let template = enrichment.format.as_str();
+    let mut metadata = String::with_capacity(template.len() + 32);
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        metadata.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            metadata.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        match &after[..end] {
+            "topic" => metadata.push_str(&record.topic),
+            "partition" => metadata.push_str(&record.partition.to_string()),
+            "offset" => metadata.push_str(&record.offset.to_string()),
+            "key" => metadata.push_str(record.key.as_deref().unwrap_or("-")),
+            "headers" => metadata.push_str(&headers),
+            "service" => metadata.push_str(kafka_msg.service.as_deref().unwrap_or("-")),
+            "host" => metadata.push_str(kafka_msg.host.as_deref().unwrap_or("-")),
+            "trace_id" => metadata.push_str(kafka_msg.trace_id.as_deref().unwrap_or("-")),
+            "span_id" => metadata.push_str(kafka_msg.span_id.as_deref().unwrap_or("-")),
+            // 未知的占位符原样保留
+            _ => metadata.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &after[end + 1..];
+    }
+    metadata.push_str(rest);
+    metadata
+}
+
 // 判断是否为连接错误
 fn is_connection_error(error_msg: &str) -> bool {
     let connection_errors = [
@@ -659,4 +1321,78 @@ fn is_connection_error(error_msg: &str) -> bool {
     ];
 
     connection_errors.iter().any(|err| error_msg.to_lowercase().contains(err))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_metadata_does_not_expand_inserted_values() {
+        let mut record = testutil::record("app", 3, 42, b"");
+        record.key = Some("{offset}".to_string());
+        record.headers = vec![("trace".to_string(), "{headers}{topic}".to_string())];
+        let message = KafkaMessage::new(LEVEL_INFO.to_string(), String::new());
+        let enrichment = EnrichmentConfig {
+            enabled: true,
+            format: "[{topic}:{partition}@{offset} key={key}{headers} {unknown}]".to_string(),
+            headers: vec!["trace".to_string()],
+        };
+
+        assert_eq!(
+            render_record_metadata(&record, &message, &enrichment),
+            "[app:3@42 key={offset} trace={headers}{topic} {unknown}]"
+        );
+    }
+
+    #[test]
+    fn route_names_cannot_leave_the_log_directory() {
+        assert_eq!(sanitize_route_name("svc-1.prod_x").as_deref(), Some("svc-1.prod_x"));
+        assert_eq!(sanitize_route_name("  web  ").as_deref(), Some("web"));
+        // 路径分隔符和绝对路径
+        assert_eq!(sanitize_route_name("../etc").as_deref(), Some(".._etc"));
+        assert_eq!(sanitize_route_name("a/../../b").as_deref(), Some("a_.._.._b"));
+        assert_eq!(sanitize_route_name("/var/log").as_deref(), Some("_var_log"));
+        assert_eq!(sanitize_route_name("C:\\Windows").as_deref(), Some("C__Windows"));
+        assert_eq!(sanitize_route_name("服务").as_deref(), Some("__"));
+        // 空名和只有点的名字没有可用的目录
+        for name in ["", "   ", ".", "..", "..."] {
+            assert_eq!(sanitize_route_name(name), None, "{:?}", name);
+        }
+        // 与年份目录区分，过长时截断
+        assert_eq!(sanitize_route_name("2026").as_deref(), Some("_2026"));
+        assert_eq!(sanitize_route_name(&"x".repeat(100)).map(|name| name.len()), Some(MAX_ROUTE_NAME_LEN));
+    }
+
+    #[test]
+    fn routes_fall_back_when_the_value_is_missing_or_invalid() {
+        let routing = |by: &str, field: Option<&str>| RoutingConfig {
+            enabled: true,
+            by: by.to_string(),
+            field: field.map(str::to_string),
+            ..Default::default()
+        };
+        let mut record = testutil::record("app", 0, 0, b"");
+        let mut message = KafkaMessage::new(LEVEL_INFO.to_string(), String::new());
+
+        assert_eq!(resolve_route(&record, &message, &routing("topic", None)), "app");
+        // 没有key、字段不存在、未知的路由依据
+        assert_eq!(resolve_route(&record, &message, &routing("key", None)), DEFAULT_ROUTE_FALLBACK);
+        assert_eq!(resolve_route(&record, &message, &routing("field", Some("service"))), DEFAULT_ROUTE_FALLBACK);
+        assert_eq!(resolve_route(&record, &message, &routing("field", None)), DEFAULT_ROUTE_FALLBACK);
+        assert_eq!(resolve_route(&record, &message, &routing("header", None)), DEFAULT_ROUTE_FALLBACK);
+
+        record.key = Some("../../etc/passwd".to_string());
+        assert_eq!(resolve_route(&record, &message, &routing("key", None)), ".._.._etc_passwd");
+        record.key = Some("..".to_string());
+        let custom = RoutingConfig {
+            fallback: "misc".to_string(),
+            ..routing("key", None)
+        };
+        assert_eq!(resolve_route(&record, &message, &custom), "misc");
+
+        message.service = Some("billing".to_string());
+        assert_eq!(resolve_route(&record, &message, &routing("field", Some("service"))), "billing");
+        message.service = Some("  ".to_string());
+        assert_eq!(resolve_route(&record, &message, &routing("field", Some("service"))), DEFAULT_ROUTE_FALLBACK);
+    }
+}
@@ -0,0 +1,181 @@
+// 维护任务：压缩、存储报告和完整性检查，由调度器按 logging.jobs 执行
+//
+//   compress  - 把已结束时间段的日志压缩为 原文件名.gz，压缩完成且原文件未再变化时才替换
+//   report    - 按服务统计文件数和容量，连同磁盘状态输出到日志
+//   integrity - 检查已结束时间段的文件末尾是否有不完整的行、压缩文件能否完整解压
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::{clock::Clock, disk, layout, quota};
+
+// 压缩已结束时间段的日志文件
+pub(crate) async fn compress_sweep(log_path: &str, clock: &Clock, layout: &layout::Layout) {
+    let root = Path::new(log_path);
+    let now = clock.now().naive_local();
+    let mut compressed = 0;
+    let mut saved: u64 = 0;
+
+    for file in layout.scan(root) {
+        if file.compressed || file.end > now {
+            continue; // 已压缩，或是正在写入的时间段
+        }
+        match compress_file(&file) {
+            Ok(Some(size)) => {
+                compressed += 1;
+                saved += file.size.saturating_sub(size);
+            }
+            Ok(None) => {
+                tklog::async_info!(
+                    "maintenance|",
+                    &format!("压缩期间文件有新的写入，下次再压缩: {:?}", file.path)
+                );
+            }
+            Err(e) => {
+                tklog::async_error!("maintenance|", &format!("压缩文件失败 {:?}: {}", file.path, e));
+            }
+        }
+    }
+
+    if compressed > 0 {
+        tklog::async_info!(
+            "maintenance|",
+            &format!("压缩完成，压缩了{}个文件，节省 {} 字节", compressed, saved)
+        );
+    }
+}
+
+// 压缩为 .gz 并删除原文件，返回压缩后的大小；压缩期间原文件有新的写入时返回None
+// 已有同名 .gz（时间段结束后又有写入）时追加为新的gzip成员
+fn compress_file(file: &layout::LayoutFile) -> io::Result<Option<u64>> {
+    let target = suffixed(&file.path, layout::COMPRESSED_SUFFIX);
+    let temp = suffixed(&target, ".tmp");
+
+    let result = (|| {
+        let mut output = File::create(&temp)?;
+        if target.exists() {
+            io::copy(&mut File::open(&target)?, &mut output)?;
+        }
+        let mut encoder = GzEncoder::new(output, Compression::default());
+        io::copy(&mut File::open(&file.path)?, &mut encoder)?;
+        let output = encoder.finish()?;
+        output.sync_all()?;
+        output.metadata().map(|m| m.len())
+    })();
+    let size = match result {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+    };
+
+    // 替换期间写入方等待，原文件大小变化说明压缩期间有新的写入
+    let _guard = layout::maintenance_guard();
+    if fs::metadata(&file.path)?.len() != file.size {
+        let _ = fs::remove_file(&temp);
+        return Ok(None);
+    }
+    fs::rename(&temp, &target)?;
+    fs::remove_file(&file.path)?;
+    Ok(Some(size))
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// 按服务统计文件数和容量
+pub(crate) async fn storage_report(log_path: &str, layout: &layout::Layout) {
+    let files = layout.scan(Path::new(log_path));
+    let total: u64 = files.iter().map(|f| f.size).sum();
+    let compressed = files.iter().filter(|f| f.compressed).count();
+    let oldest = files.iter().map(|f| f.start).min();
+
+    let mut per_service: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+    for file in &files {
+        let entry = per_service.entry(file.service()).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += file.size;
+    }
+    let services = per_service
+        .iter()
+        .map(|(service, (count, size))| {
+            format!("{}={}个/{}字节", quota::display_service(service), count, size)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut report = format!(
+        "存储报告: {}个文件（已压缩{}个），共 {} 字节",
+        files.len(),
+        compressed,
+        total
+    );
+    if let Some(oldest) = oldest {
+        report.push_str(&format!("，最早 {}", oldest.format("%Y-%m-%d %H:%M")));
+    }
+    if !services.is_empty() {
+        report.push_str(&format!("；按服务: {}", services));
+    }
+    if let Some(disk) = disk::describe() {
+        report.push_str(&format!("；{}", disk));
+    }
+    tklog::async_info!("report|", &report);
+}
+
+// 检查已结束时间段的文件：普通文件末尾应为换行，压缩文件应能完整解压
+pub(crate) async fn integrity_check(log_path: &str, clock: &Clock, layout: &layout::Layout) {
+    let now = clock.now().naive_local();
+    let mut checked = 0;
+    let mut problems = 0;
+
+    for file in layout.scan(Path::new(log_path)) {
+        if file.end > now {
+            continue; // 正在写入的时间段
+        }
+        checked += 1;
+        let result = if file.compressed {
+            File::open(&file.path)
+                .and_then(|f| io::copy(&mut MultiGzDecoder::new(f), &mut io::sink()))
+                .map(|_| None)
+                .map_err(|e| format!("无法完整解压: {}", e))
+        } else {
+            ends_with_newline(&file.path)
+                .map(|complete| (!complete).then_some("末尾有不完整的行".to_string()))
+                .map_err(|e| format!("读取失败: {}", e))
+        };
+
+        let problem = match result {
+            Ok(problem) => problem,
+            Err(e) => Some(e),
+        };
+        if let Some(problem) = problem {
+            problems += 1;
+            tklog::async_warn!("integrity|", &format!("{:?}: {}", file.path, problem));
+        }
+    }
+
+    tklog::async_info!(
+        "integrity|",
+        &format!("完整性检查完成，检查了{}个文件，发现{}个问题", checked, problems)
+    );
+}
+
+fn ends_with_newline(path: &Path) -> io::Result<bool> {
+    let mut file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(true);
+    }
+    file.seek(SeekFrom::End(-1))?;
+    let mut last = [0u8; 1];
+    file.read_exact(&mut last)?;
+    Ok(last[0] == b'\n')
+}
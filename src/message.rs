@@ -0,0 +1,671 @@
+// 日志消息结构及字段访问
+//
+// 必填字段仍是 L（级别）和 S（内容），兼容只有这两个字段的旧格式；
+// 另外支持若干可选的通用字段，其余字段原样保存在 extra 中，
+// 供路由、规则和输出格式使用。
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use prost::Message as _;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor, SerializeOptions};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::sanitize::decode_utf8;
+use crate::LogLevel;
+
+const DEFAULT_MESSAGE_FORMAT: &str = "json";
+
+// 纯文本模式的默认级别识别规则，命名分组 level 为级别
+// 依次为: 行首 [ERROR]、level=warn、glog风格 E1016
+const DEFAULT_LEVEL_PATTERNS: [&str; 3] = [
+    r"^\s*\[(?P<level>[A-Za-z]+)\]",
+    r"\blevel=(?P<level>[A-Za-z]+)\b",
+    r"^(?P<level>[IWEF])\d{4}\s",
+];
+
+// JSON消息结构体
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(from = "RawMessage")]
+pub(crate) struct KafkaMessage {
+    pub(crate) l: String, // 日志级别
+    pub(crate) s: String, // 日志内容
+    pub(crate) service: Option<String>,
+    pub(crate) host: Option<String>,
+    pub(crate) trace_id: Option<String>,
+    pub(crate) span_id: Option<String>,
+    pub(crate) timestamp: Option<String>, // 生产者时间戳，字符串或数字
+    pub(crate) extra: serde_json::Map<String, serde_json::Value>, // 其余结构化字段
+}
+
+// 反序列化的原始结构：通用字段先和其他字段一起收集，再按取值类型拆分
+#[derive(serde::Deserialize)]
+struct RawMessage {
+    #[serde(rename = "L")]
+    l: String,
+    #[serde(rename = "S")]
+    s: String,
+    #[serde(flatten)]
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl From<RawMessage> for KafkaMessage {
+    fn from(raw: RawMessage) -> Self {
+        let mut extra = raw.fields;
+        let mut take = |name: &str| scalar_field(&mut extra, name);
+        KafkaMessage {
+            l: raw.l,
+            s: raw.s,
+            service: take("service"),
+            host: take("host"),
+            trace_id: take("trace_id"),
+            span_id: take("span_id"),
+            timestamp: take("timestamp"),
+            extra,
+        }
+    }
+}
+
+impl KafkaMessage {
+    // 只有级别和内容的消息
+    pub(crate) fn new(level: String, content: String) -> Self {
+        KafkaMessage {
+            l: level,
+            s: content,
+            service: None,
+            host: None,
+            trace_id: None,
+            span_id: None,
+            timestamp: None,
+            extra: serde_json::Map::new(),
+        }
+    }
+
+    // 按名称读取字段：先查通用字段，再查 extra（支持 a.b.c 形式的嵌套路径）
+    pub(crate) fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        let known = match name {
+            "L" | "level" => Some(&self.l),
+            "S" | "content" => Some(&self.s),
+            "service" => self.service.as_ref(),
+            "host" => self.host.as_ref(),
+            "trace_id" => self.trace_id.as_ref(),
+            "span_id" => self.span_id.as_ref(),
+            "timestamp" => self.timestamp.as_ref(),
+            _ => None,
+        };
+        if let Some(value) = known {
+            return Some(Cow::Borrowed(value.as_str()));
+        }
+
+        let value = lookup_path(&self.extra, name)?;
+        match value {
+            serde_json::Value::String(s) => Some(Cow::Borrowed(s.as_str())),
+            serde_json::Value::Null => None,
+            other => Some(Cow::Owned(other.to_string())),
+        }
+    }
+}
+
+// 先按完整名称查找，找不到再按 . 拆分逐层查找
+fn lookup_path<'a>(
+    map: &'a serde_json::Map<String, serde_json::Value>,
+    path: &str,
+) -> Option<&'a serde_json::Value> {
+    if let Some(value) = map.get(path) {
+        return Some(value);
+    }
+
+    let mut parts = path.split('.');
+    let mut current = map.get(parts.next()?)?;
+    for part in parts {
+        current = current.get(part)?;
+    }
+    Some(current)
+}
+
+// 字符串、数字、布尔值都接受为字符串，null视为缺失
+// 对象和数组（如结构化的 host）不作为通用字段，原样保留在 extra 中
+fn scalar_field(extra: &mut serde_json::Map<String, serde_json::Value>, name: &str) -> Option<String> {
+    let value = match extra.get(name)? {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        serde_json::Value::Array(_) | serde_json::Value::Object(_) => return None,
+    };
+    extra.remove(name);
+    value
+}
+
+// 每个来源（主题）的消息格式配置
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct SourceConfig {
+    #[serde(default = "default_message_format")]
+    pub(crate) format: String, // 消息格式: json / text / msgpack / protobuf
+    #[serde(default)]
+    pub(crate) mapping: Option<FieldMapping>, // 非标准消息结构的字段映射
+    #[serde(default)]
+    pub(crate) default_level: Option<String>, // 消息中没有级别时使用，默认INFO
+    #[serde(default)]
+    pub(crate) level_patterns: Vec<String>, // text模式的级别识别正则，需包含命名分组 level
+    #[serde(default)]
+    pub(crate) descriptor_set: Option<String>, // protobuf自定义结构：FileDescriptorSet文件路径
+    #[serde(default)]
+    pub(crate) message_type: Option<String>, // protobuf自定义结构：完整消息类型名，如 "acme.log.Entry"
+}
+
+// protobuf内置日志结构，对应 proto/log_record.proto
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct ProtoLogRecord {
+    #[prost(string, tag = "1")]
+    pub(crate) level: String,
+    #[prost(string, tag = "2")]
+    pub(crate) content: String,
+    #[prost(string, optional, tag = "3")]
+    pub(crate) service: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub(crate) host: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub(crate) trace_id: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub(crate) span_id: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub(crate) timestamp: Option<String>,
+    #[prost(map = "string, string", tag = "8")]
+    pub(crate) fields: HashMap<String, String>,
+}
+
+fn default_message_format() -> String {
+    DEFAULT_MESSAGE_FORMAT.to_string()
+}
+
+// 字段映射：值为JSON路径，嵌套字段用 . 分隔，例如 "meta.service"
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub(crate) struct FieldMapping {
+    #[serde(default)]
+    pub(crate) level: Option<String>,
+    #[serde(default)]
+    pub(crate) content: Option<String>,
+    #[serde(default)]
+    pub(crate) timestamp: Option<String>,
+    #[serde(default)]
+    pub(crate) service: Option<String>,
+    #[serde(default)]
+    pub(crate) host: Option<String>,
+    #[serde(default)]
+    pub(crate) trace_id: Option<String>,
+    #[serde(default)]
+    pub(crate) span_id: Option<String>,
+}
+
+#[derive(Debug)]
+enum SourceFormat {
+    Json,
+    Text(Vec<Regex>),
+    MsgPack,
+    Protobuf(Option<MessageDescriptor>), // None 表示使用内置结构
+}
+
+#[derive(Debug)]
+struct SourceDecoder {
+    format: SourceFormat,
+    mapping: Option<FieldMapping>,
+    default_level: String,
+}
+
+// 消息解码器：按来源（主题）选择格式和字段映射，未配置的来源按标准JSON格式解析
+#[derive(Debug, Default)]
+pub(crate) struct Decoder {
+    sources: HashMap<String, SourceDecoder>,
+}
+
+impl Decoder {
+    pub(crate) fn new(
+        configs: &HashMap<String, SourceConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut sources = HashMap::with_capacity(configs.len());
+
+        for (topic, config) in configs {
+            let default_level = config.default_level.as_deref().unwrap_or(crate::LEVEL_INFO);
+            if LogLevel::from_str(default_level).is_none() {
+                return Err(format!("来源 {} 的 default_level 无效: {}", topic, default_level).into());
+            }
+
+            let format = match config.format.as_str() {
+                "json" => SourceFormat::Json,
+                "text" => {
+                    let patterns: Vec<&str> = if config.level_patterns.is_empty() {
+                        DEFAULT_LEVEL_PATTERNS.to_vec()
+                    } else {
+                        config.level_patterns.iter().map(String::as_str).collect()
+                    };
+                    let mut compiled = Vec::with_capacity(patterns.len());
+                    for pattern in patterns {
+                        let regex = Regex::new(pattern)
+                            .map_err(|e| format!("来源 {} 的级别正则无效: {}", topic, e))?;
+                        if !regex.capture_names().any(|name| name == Some("level")) {
+                            return Err(format!(
+                                "来源 {} 的级别正则缺少命名分组 level: {}",
+                                topic, pattern
+                            )
+                            .into());
+                        }
+                        compiled.push(regex);
+                    }
+                    SourceFormat::Text(compiled)
+                }
+                "msgpack" => SourceFormat::MsgPack,
+                "protobuf" => SourceFormat::Protobuf(load_message_descriptor(topic, config)?),
+                other => return Err(format!("来源 {} 的消息格式无效: {}", topic, other).into()),
+            };
+
+            sources.insert(
+                topic.clone(),
+                SourceDecoder {
+                    format,
+                    mapping: config.mapping.clone(),
+                    default_level: default_level.to_string(),
+                },
+            );
+        }
+
+        Ok(Decoder { sources })
+    }
+
+    // 来源是否为二进制格式（截断后的载荷无法解码）
+    pub(crate) fn is_binary(&self, topic: &str) -> bool {
+        self.sources
+            .get(topic)
+            .is_some_and(|source| matches!(source.format, SourceFormat::MsgPack | SourceFormat::Protobuf(_)))
+    }
+
+    // 超限记录截断后的原始文本作为一个条目：文本来源照常按规则识别级别，
+    // 其他来源（截断后的JSON无法解析）使用默认级别
+    pub(crate) fn decode_truncated(&self, topic: &str, content: String) -> KafkaMessage {
+        match self.sources.get(topic) {
+            Some(source) => match source.format {
+                SourceFormat::Text(ref patterns) => decode_text(&content, patterns, source),
+                _ => KafkaMessage::new(source.default_level.clone(), content),
+            },
+            None => KafkaMessage::new(crate::LEVEL_INFO.to_string(), content),
+        }
+    }
+
+    // 按来源配置把原始载荷解码为统一的消息结构
+    // JSON格式的载荷可以是单个对象、对象数组或按行分隔的JSON（NDJSON），
+    // msgpack格式可以是单个map或map数组；每个条目单独解码，
+    // 解码失败的条目不影响同一批次的其他条目
+    pub(crate) fn decode_batch(&self, topic: &str, payload: &[u8]) -> Vec<Result<KafkaMessage, String>> {
+        let source = self.sources.get(topic);
+        let Some(source_decoder) = source else {
+            return decode_json_batch(&decode_utf8(payload), None);
+        };
+
+        match source_decoder.format {
+            SourceFormat::Json => decode_json_batch(&decode_utf8(payload), source),
+            SourceFormat::Text(ref patterns) => vec![Ok(decode_text(
+                &decode_utf8(payload),
+                patterns,
+                source_decoder,
+            ))],
+            SourceFormat::MsgPack => decode_msgpack_batch(payload, source_decoder),
+            SourceFormat::Protobuf(ref descriptor) => {
+                vec![decode_protobuf(payload, descriptor.as_ref(), source_decoder)]
+            }
+        }
+    }
+}
+
+// 读取protobuf自定义结构的描述符；未配置 descriptor_set 时使用内置结构
+fn load_message_descriptor(
+    topic: &str,
+    config: &SourceConfig,
+) -> Result<Option<MessageDescriptor>, Box<dyn std::error::Error>> {
+    let Some(ref path) = config.descriptor_set else {
+        return Ok(None);
+    };
+    let message_type = config
+        .message_type
+        .as_deref()
+        .ok_or_else(|| format!("来源 {} 配置了 descriptor_set 但缺少 message_type", topic))?;
+
+    let bytes = std::fs::read(path)
+        .map_err(|e| format!("来源 {} 的描述符文件读取失败: {} - {}", topic, path, e))?;
+    let pool = DescriptorPool::decode(bytes.as_slice())
+        .map_err(|e| format!("来源 {} 的描述符文件解析失败: {} - {}", topic, path, e))?;
+    let descriptor = pool
+        .get_message_by_name(message_type)
+        .ok_or_else(|| format!("来源 {} 的描述符中找不到消息类型: {}", topic, message_type))?;
+
+    Ok(Some(descriptor))
+}
+
+fn decode_msgpack_batch(payload: &[u8], source: &SourceDecoder) -> Vec<Result<KafkaMessage, String>> {
+    match rmp_serde::from_slice::<serde_json::Value>(payload) {
+        Ok(serde_json::Value::Array(items)) => items
+            .into_iter()
+            .map(|item| decode_json_value(item, Some(source)))
+            .collect(),
+        Ok(value) => vec![decode_json_value(value, Some(source))],
+        Err(e) => vec![Err(format!("解析msgpack消息失败: {} - 长度: {}字节", e, payload.len()))],
+    }
+}
+
+fn decode_protobuf(
+    payload: &[u8],
+    descriptor: Option<&MessageDescriptor>,
+    source: &SourceDecoder,
+) -> Result<KafkaMessage, String> {
+    let parse_error = |e: prost::DecodeError| format!("解析protobuf消息失败: {} - 长度: {}字节", e, payload.len());
+
+    // 自定义结构：转换为JSON后走字段映射
+    if let Some(descriptor) = descriptor {
+        let message = DynamicMessage::decode(descriptor.clone(), payload).map_err(parse_error)?;
+        let options = SerializeOptions::new().use_proto_field_name(true);
+        let value = message
+            .serialize_with_options(serde_json::value::Serializer, &options)
+            .map_err(|e| format!("解析protobuf消息失败: {}", e))?;
+        return decode_json_value(value, Some(source));
+    }
+
+    let record = ProtoLogRecord::decode(payload).map_err(parse_error)?;
+    let level = if record.level.is_empty() {
+        source.default_level.clone()
+    } else {
+        record.level
+    };
+
+    Ok(KafkaMessage {
+        l: level,
+        s: record.content,
+        service: record.service,
+        host: record.host,
+        trace_id: record.trace_id,
+        span_id: record.span_id,
+        timestamp: record.timestamp,
+        extra: record
+            .fields
+            .into_iter()
+            .map(|(k, v)| (k, serde_json::Value::String(v)))
+            .collect(),
+    })
+}
+
+fn decode_json_batch(payload: &str, source: Option<&SourceDecoder>) -> Vec<Result<KafkaMessage, String>> {
+    match serde_json::from_str::<serde_json::Value>(payload) {
+        Ok(serde_json::Value::Array(items)) => items
+            .into_iter()
+            .map(|item| decode_json_value(item, source))
+            .collect(),
+        Ok(value) => vec![decode_json_value(value, source)],
+        Err(e) => {
+            // 整体不是合法JSON时按NDJSON逐行解析
+            let lines: Vec<&str> = payload.lines().filter(|line| !line.trim().is_empty()).collect();
+            if lines.len() <= 1 {
+                return vec![Err(format!("解析Kafka消息失败: {} - 原始消息: {}", e, payload))];
+            }
+            lines
+                .into_iter()
+                .map(|line| {
+                    serde_json::from_str(line)
+                        .map_err(|e| format!("解析Kafka消息失败: {} - 原始消息: {}", e, line))
+                        .and_then(|value| decode_json_value(value, source))
+                })
+                .collect()
+        }
+    }
+}
+
+fn decode_json_value(
+    value: serde_json::Value,
+    source: Option<&SourceDecoder>,
+) -> Result<KafkaMessage, String> {
+    let parse_error =
+        |e: serde_json::Error, value: &serde_json::Value| format!("解析Kafka消息失败: {} - 原始消息: {}", e, value);
+
+    let Some(source) = source else {
+        return KafkaMessage::deserialize(&value).map_err(|e| parse_error(e, &value));
+    };
+
+    let serde_json::Value::Object(mut object) = value else {
+        return Err(format!("解析Kafka消息失败: 消息不是JSON对象 - 原始消息: {}", value));
+    };
+
+    if let Some(ref mapping) = source.mapping {
+        apply_mapping(&mut object, mapping);
+    }
+    if !object.contains_key("L") {
+        object.insert(
+            "L".to_string(),
+            serde_json::Value::String(source.default_level.clone()),
+        );
+    }
+
+    let value = serde_json::Value::Object(object);
+    KafkaMessage::deserialize(&value).map_err(|e| parse_error(e, &value))
+}
+
+// 纯文本模式：整条载荷作为内容，级别按规则依次识别，都不匹配时使用默认级别
+fn decode_text(payload: &str, patterns: &[Regex], source: &SourceDecoder) -> KafkaMessage {
+    let content = payload.trim_end_matches(['\r', '\n']);
+
+    let level = patterns
+        .iter()
+        .filter_map(|pattern| pattern.captures(content))
+        .filter_map(|caps| caps.name("level"))
+        .find_map(|m| LogLevel::from_alias(m.as_str()))
+        .map(|level| level.as_str().to_string())
+        .unwrap_or_else(|| source.default_level.clone());
+
+    KafkaMessage::new(level, content.to_string())
+}
+
+// 把映射的字段搬到标准字段名上；顶层字段搬走后不再出现在 extra 中
+fn apply_mapping(object: &mut serde_json::Map<String, serde_json::Value>, mapping: &FieldMapping) {
+    let targets = [
+        ("L", &mapping.level),
+        ("S", &mapping.content),
+        ("timestamp", &mapping.timestamp),
+        ("service", &mapping.service),
+        ("host", &mapping.host),
+        ("trace_id", &mapping.trace_id),
+        ("span_id", &mapping.span_id),
+    ];
+
+    for (target, path) in targets {
+        let Some(path) = path.as_deref() else {
+            continue;
+        };
+        let value = if object.contains_key(path) {
+            object.remove(path)
+        } else {
+            lookup_path(object, path).cloned()
+        };
+        let Some(value) = value else {
+            continue;
+        };
+
+        // 级别和内容必须是字符串
+        let value = match (target, value) {
+            ("L" | "S", serde_json::Value::String(s)) => serde_json::Value::String(s),
+            ("L" | "S", other) => serde_json::Value::String(other.to_string()),
+            (_, other) => other,
+        };
+        object.insert(target.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structured_common_fields_stay_in_extra() {
+        let decoded = decode_json_batch(
+            r#"{"L":"INFO","S":"hi","host":{"name":"web-1"},"service":["a"],"trace_id":7,"span_id":null}"#,
+            None,
+        );
+        let message = decoded.into_iter().next().unwrap().unwrap();
+
+        assert_eq!(message.host, None);
+        assert_eq!(message.service, None);
+        assert_eq!(message.trace_id.as_deref(), Some("7"));
+        assert_eq!(message.span_id, None);
+        assert_eq!(message.extra.get("host"), Some(&serde_json::json!({"name": "web-1"})));
+        assert_eq!(message.extra.get("service"), Some(&serde_json::json!(["a"])));
+        assert!(!message.extra.contains_key("trace_id"));
+        assert_eq!(message.field("host.name").as_deref(), Some("web-1"));
+    }
+
+    // testdata 下的固定载荷，内容见各测试中的断言
+    fn testdata(name: &str) -> String {
+        format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn decoder(yaml: &str) -> Decoder {
+        let configs: HashMap<String, SourceConfig> = serde_yaml::from_str(yaml).unwrap();
+        Decoder::new(&configs).unwrap()
+    }
+
+    #[test]
+    fn msgpack_batch() {
+        let decoder = decoder("mp: { format: msgpack, default_level: WARN }");
+        let payload = std::fs::read(testdata("batch.msgpack")).unwrap();
+        let decoded: Vec<KafkaMessage> = decoder
+            .decode_batch("mp", &payload)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].l, "ERROR");
+        assert_eq!(decoded[0].s, "disk full");
+        assert_eq!(decoded[0].service.as_deref(), Some("db"));
+        assert_eq!(decoded[0].extra.get("retries"), Some(&serde_json::json!(5)));
+        // 缺少级别时使用来源的默认级别
+        assert_eq!(decoded[1].l, "WARN");
+        assert_eq!(decoded[1].s, "no level");
+    }
+
+    #[test]
+    fn protobuf_builtin_record() {
+        let decoder = decoder("pb: { format: protobuf }");
+        let payload = std::fs::read(testdata("log_record.pb")).unwrap();
+        let message = decoder.decode_batch("pb", &payload).remove(0).unwrap();
+
+        assert_eq!(message.l, "WARN");
+        assert_eq!(message.s, "slow query");
+        assert_eq!(message.service.as_deref(), Some("api"));
+        assert_eq!(message.host.as_deref(), Some("web-1"));
+        assert_eq!(message.trace_id, None);
+        assert_eq!(message.timestamp.as_deref(), Some("2026-10-18T12:00:00Z"));
+        assert_eq!(message.extra.get("region"), Some(&serde_json::json!("eu")));
+    }
+
+    #[test]
+    fn protobuf_descriptor_set() {
+        let decoder = decoder(&format!(
+            "custom: {{ format: protobuf, descriptor_set: '{}', message_type: acme.log.Entry, \
+             mapping: {{ level: severity, content: message, service: meta.service }} }}",
+            testdata("entry.desc")
+        ));
+        let payload = std::fs::read(testdata("entry.pb")).unwrap();
+        let message = decoder.decode_batch("custom", &payload).remove(0).unwrap();
+
+        assert_eq!(message.l, "ERROR");
+        assert_eq!(message.s, "boom");
+        assert_eq!(message.service.as_deref(), Some("billing"));
+        assert_eq!(message.field("meta.pid").as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn corrupt_binary_payloads() {
+        let decoder = decoder("mp: { format: msgpack }\npb: { format: protobuf }");
+
+        let msgpack = decoder.decode_batch("mp", &[0xc1]);
+        assert_eq!(msgpack.len(), 1);
+        assert!(msgpack[0].as_ref().unwrap_err().starts_with("解析msgpack消息失败"));
+
+        // 长度前缀超出载荷
+        let protobuf = decoder.decode_batch("pb", &[0x0a, 0x05, b'a']);
+        assert_eq!(protobuf.len(), 1);
+        assert!(protobuf[0].as_ref().unwrap_err().starts_with("解析protobuf消息失败"));
+    }
+
+    #[test]
+    fn field_mapping_with_missing_and_nested_keys() {
+        let decoder = decoder(
+            "m: { format: json, default_level: WARN, \
+             mapping: { level: severity, content: msg.text, service: meta.service, host: meta.host } }",
+        );
+
+        let message = decoder
+            .decode_batch("m", br#"{"severity":"ERROR","msg":{"text":"boom"},"meta":{"service":"billing"}}"#)
+            .remove(0)
+            .unwrap();
+        assert_eq!(message.l, "ERROR");
+        assert_eq!(message.s, "boom");
+        assert_eq!(message.service.as_deref(), Some("billing"));
+        // 映射的嵌套字段不存在时保持为空
+        assert_eq!(message.host, None);
+        assert!(!message.extra.contains_key("severity"));
+
+        // 没有级别时使用默认级别，没有内容时解码失败
+        let message = decoder.decode_batch("m", br#"{"msg":{"text":"x"}}"#).remove(0).unwrap();
+        assert_eq!(message.l, "WARN");
+        assert_eq!(message.s, "x");
+        assert!(decoder.decode_batch("m", br#"{"severity":"INFO"}"#).remove(0).is_err());
+        assert!(decoder.decode_batch("m", br#"{"severity":"INFO","msg":"flat"}"#).remove(0).is_err());
+    }
+
+    #[test]
+    fn text_default_level_patterns() {
+        let decoder = decoder("t: { format: text, default_level: DEBUG }");
+        let level = |payload: &str| decoder.decode_batch("t", payload.as_bytes()).remove(0).unwrap().l;
+
+        assert_eq!(level("[WARN] disk almost full"), "WARN");
+        assert_eq!(level("  [warning] alias"), "WARN");
+        assert_eq!(level("ts=1 level=error msg=boom"), "ERROR");
+        assert_eq!(level("E1018 12:00:00.000 glog style"), "ERROR");
+        assert_eq!(level("I1018 12:00:00.000 glog style"), "INFO");
+        // 都不匹配时使用默认级别
+        assert_eq!(level("plain message"), "DEBUG");
+        assert_eq!(level("[NOPE] unknown level"), "DEBUG");
+        assert_eq!(level("E10 too short"), "DEBUG");
+
+        let message = decoder.decode_batch("t", b"line\r\n").remove(0).unwrap();
+        assert_eq!(message.s, "line");
+    }
+
+    #[test]
+    fn top_level_json_array() {
+        let decoded = decode_json_batch(r#"[{"L":"INFO","S":"a"},{"L":"ERROR","S":"b"},42]"#, None);
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[0].as_ref().unwrap().s, "a");
+        assert_eq!(decoded[1].as_ref().unwrap().l, "ERROR");
+        // 数组中的非对象元素单独失败
+        assert!(decoded[2].is_err());
+
+        let decoder = decoder("j: { format: json }");
+        let decoded = decoder.decode_batch("j", br#"[{"S":"no level"},"text"]"#);
+        assert_eq!(decoded[0].as_ref().unwrap().l, "INFO");
+        assert!(decoded[1].as_ref().unwrap_err().contains("消息不是JSON对象"));
+    }
+
+    #[test]
+    fn ndjson_with_blank_and_garbled_lines() {
+        let payload = "{\"L\":\"INFO\",\"S\":\"a\"}\n\n   \n{garbled\n{\"L\":\"WARN\",\"S\":\"b\"}\n";
+        let decoded = decode_json_batch(payload, None);
+
+        // 空行被忽略，损坏的行不影响其他行
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[0].as_ref().unwrap().s, "a");
+        assert!(decoded[1].as_ref().unwrap_err().contains("{garbled"));
+        assert_eq!(decoded[2].as_ref().unwrap().s, "b");
+
+        // 只有一行时整体报错
+        let decoded = decode_json_batch("{garbled\n\n", None);
+        assert_eq!(decoded.len(), 1);
+        assert!(decoded[0].is_err());
+    }
+}
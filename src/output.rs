@@ -0,0 +1,445 @@
+// 输出格式：按模板生成文本行，或输出保留全部结构化字段的JSON行
+//
+// 模板占位符: {timestamp} {level} {level_abbr} {content} {tags} {service} {host}
+//             {trace_id} {span_id} {topic} {partition} {offset} {key} {metadata}
+// {metadata} 按 enrichment.format 渲染；{content} 按多行策略编码
+// 默认模板 `[{timestamp}] [{level_abbr}] {content}` 与 read 子命令兼容
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::{
+    format, get_level_abbreviation, render_record_metadata, sanitize, sanitize_route_name,
+    KafkaMessage, KafkaRecord, LogLevel, LoggingConfig,
+};
+
+const OUTPUT_TEXT: &str = "text";
+const OUTPUT_JSON: &str = "json";
+const DEFAULT_OUTPUT_TEMPLATE: &str = "[{timestamp}] [{level_abbr}] {content}";
+
+const OUTPUT_FORMAT_ERROR: &str = "output.format 只能是 text 或 json";
+const OUTPUT_TEMPLATE_ERROR: &str = "output.template 必须包含 {content}，且不能包含换行";
+const OUTPUT_ROUTE_ERROR: &str = "outputs 的键必须是合法的路由目录名（字母、数字、.、_、-）";
+
+const TEMPLATE_PLACEHOLDERS: [&str; 14] = [
+    "timestamp",
+    "level",
+    "level_abbr",
+    "content",
+    "tags",
+    "service",
+    "host",
+    "trace_id",
+    "span_id",
+    "topic",
+    "partition",
+    "offset",
+    "key",
+    "metadata",
+];
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct OutputConfig {
+    #[serde(default = "default_output_format")]
+    pub(crate) format: String, // text / json
+    #[serde(default = "default_output_template")]
+    pub(crate) template: String, // text格式的行模板
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            format: default_output_format(),
+            template: default_output_template(),
+        }
+    }
+}
+
+impl OutputConfig {
+    pub(crate) fn is_json(&self) -> bool {
+        self.format == OUTPUT_JSON
+    }
+}
+
+fn default_output_format() -> String {
+    OUTPUT_TEXT.to_string()
+}
+
+fn default_output_template() -> String {
+    DEFAULT_OUTPUT_TEMPLATE.to_string()
+}
+
+pub(crate) fn validate_outputs(
+    output: &OutputConfig,
+    outputs: &HashMap<String, OutputConfig>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    validate_output(output)?;
+    for (route, output) in outputs {
+        if sanitize_route_name(route).as_deref() != Some(route.as_str()) {
+            return Err(format!("{}: {}", OUTPUT_ROUTE_ERROR, route).into());
+        }
+        validate_output(output).map_err(|e| format!("outputs.{}: {}", route, e))?;
+    }
+    Ok(())
+}
+
+fn validate_output(output: &OutputConfig) -> Result<(), Box<dyn std::error::Error>> {
+    match output.format.as_str() {
+        OUTPUT_JSON => Ok(()),
+        OUTPUT_TEXT => {
+            if !output.template.contains("{content}") || output.template.contains(['\n', '\r']) {
+                return Err(OUTPUT_TEMPLATE_ERROR.into());
+            }
+            // 未知占位符在启动时报错，避免原样写入文件
+            let mut rest = output.template.as_str();
+            while let Some(start) = rest.find('{') {
+                let Some(end) = rest[start..].find('}') else {
+                    break;
+                };
+                let name = &rest[start + 1..start + end];
+                if !TEMPLATE_PLACEHOLDERS.contains(&name) {
+                    return Err(format!("output.template 未知占位符: {{{}}}", name).into());
+                }
+                rest = &rest[start + end + 1..];
+            }
+            Ok(())
+        }
+        _ => Err(OUTPUT_FORMAT_ERROR.into()),
+    }
+}
+
+// 一条待写入的日志及其结构化来源
+pub(crate) struct LogEntry<'a> {
+    pub(crate) time: &'a DateTime<FixedOffset>, // 决定写入的文件
+    pub(crate) timestamp: &'a str,              // 按精度格式化后的行时间戳
+    pub(crate) source_timestamp: Option<&'a str>, // 规范化后的生产者时间戳
+    pub(crate) level: &'a str,
+    pub(crate) content: &'a str,
+    pub(crate) tags: &'a [String],
+    pub(crate) record: Option<&'a KafkaRecord>,
+    pub(crate) message: Option<&'a KafkaMessage>,
+}
+
+impl<'a> LogEntry<'a> {
+    // 服务器自身的日志，没有Kafka来源
+    pub(crate) fn plain(
+        time: &'a DateTime<FixedOffset>,
+        timestamp: &'a str,
+        level: &'a str,
+        content: &'a str,
+    ) -> Self {
+        LogEntry {
+            time,
+            timestamp,
+            source_timestamp: None,
+            level,
+            content,
+            tags: &[],
+            record: None,
+            message: None,
+        }
+    }
+}
+
+impl LogEntry<'_> {
+    // 目录布局占位符的取值（{route} 由调用方提供）
+    pub(crate) fn layout_field(&self, name: &str) -> Option<String> {
+        match name {
+            "level" => Some(level_name(self.level).to_string()),
+            "service" => self.message?.service.clone(),
+            "host" => self.message?.host.clone(),
+            "topic" => self.record.map(|r| r.topic.clone()),
+            _ => None,
+        }
+    }
+}
+
+// 按路由选择输出格式，未单独配置的路由使用 logging.output
+pub(crate) fn output_for<'a>(logging: &'a LoggingConfig, route: Option<&str>) -> &'a OutputConfig {
+    route
+        .and_then(|route| logging.outputs.get(route))
+        .unwrap_or(&logging.output)
+}
+
+// 生成完整的一行（含结尾换行）
+pub(crate) fn render_line(
+    logging: &LoggingConfig,
+    output: &OutputConfig,
+    entry: &LogEntry,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if output.format == OUTPUT_JSON {
+        render_json(logging, entry)
+    } else {
+        Ok(render_text(logging, output, entry))
+    }
+}
+
+fn render_text(logging: &LoggingConfig, output: &OutputConfig, entry: &LogEntry) -> String {
+    let template = output.template.as_str();
+
+    // 模板没有单独的 {tags}/{metadata} 时，标签放在内容前面，元数据按配置追加在末尾
+    let mut content = if entry.tags.is_empty() || template.contains("{tags}") {
+        entry.content.to_string()
+    } else {
+        format!("[{}] {}", entry.tags.join(","), entry.content)
+    };
+    if logging.enrichment.enabled && !template.contains("{metadata}") {
+        if let Some(metadata) = metadata(logging, entry) {
+            content.push(' ');
+            content.push_str(&metadata);
+        }
+    }
+
+    // 转义控制字符和ANSI序列，防止伪造日志行或劫持终端
+    let multiline = &logging.multiline;
+    let content = clean(logging, &content, format::handles_newlines(multiline));
+    if template == DEFAULT_OUTPUT_TEMPLATE {
+        return format::format_line(
+            entry.timestamp,
+            get_level_abbreviation(entry.level),
+            &content,
+            multiline,
+        );
+    }
+
+    // 单次扫描替换，内容中的 {xxx} 不会被再次展开
+    let mut line = String::with_capacity(template.len() + content.len() + 32);
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        line.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            line.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let name = &after[..end];
+        match name {
+            "content" => line.push_str(&format::encode_content(&content, multiline)),
+            _ => line.push_str(&placeholder(logging, entry, name)),
+        }
+        rest = &after[end + 1..];
+    }
+    line.push_str(rest);
+    line.push('\n');
+    line
+}
+
+fn placeholder<'a>(logging: &LoggingConfig, entry: &LogEntry<'a>, name: &str) -> Cow<'a, str> {
+    let message_field = |value: Option<&'a String>| match value {
+        Some(value) => clean(logging, value, false),
+        None => Cow::Borrowed("-"),
+    };
+    let message = entry.message;
+    let record = entry.record;
+
+    match name {
+        "timestamp" => Cow::Borrowed(entry.timestamp),
+        "level" => Cow::Borrowed(level_name(entry.level)),
+        "level_abbr" => Cow::Borrowed(get_level_abbreviation(entry.level)),
+        "tags" => Cow::Owned(entry.tags.join(",")),
+        "service" => message_field(message.and_then(|m| m.service.as_ref())),
+        "host" => message_field(message.and_then(|m| m.host.as_ref())),
+        "trace_id" => message_field(message.and_then(|m| m.trace_id.as_ref())),
+        "span_id" => message_field(message.and_then(|m| m.span_id.as_ref())),
+        "topic" => Cow::Borrowed(record.map_or("-", |r| r.topic.as_str())),
+        "partition" => record.map_or(Cow::Borrowed("-"), |r| Cow::Owned(r.partition.to_string())),
+        "offset" => record.map_or(Cow::Borrowed("-"), |r| Cow::Owned(r.offset.to_string())),
+        "key" => message_field(record.and_then(|r| r.key.as_ref())),
+        "metadata" => match metadata(logging, entry) {
+            Some(metadata) => Cow::Owned(clean(logging, &metadata, false).into_owned()),
+            None => Cow::Borrowed(""),
+        },
+        _ => Cow::Borrowed(""),
+    }
+}
+
+// JSON行：保留全部结构化字段，换行和控制字符由JSON转义
+#[derive(serde::Serialize)]
+struct JsonLine<'a> {
+    timestamp: &'a str,
+    level: &'a str,
+    content: Cow<'a, str>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    tags: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    span_id: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_timestamp: Option<Cow<'a, str>>, // 生产者时间戳
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<Cow<'a, serde_json::Map<String, serde_json::Value>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kafka: Option<JsonKafka<'a>>,
+}
+
+#[derive(serde::Serialize)]
+struct JsonKafka<'a> {
+    topic: &'a str,
+    partition: i32,
+    offset: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<Cow<'a, str>, Cow<'a, str>>,
+}
+
+fn render_json<'a>(
+    logging: &LoggingConfig,
+    entry: &LogEntry<'a>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // JSON转义已处理换行，清洗只需处理其余控制字符和ANSI序列
+    let field = |value: Option<&'a String>| value.map(|value| clean(logging, value, true));
+    let message = entry.message;
+
+    let line = JsonLine {
+        timestamp: entry.timestamp,
+        level: level_name(entry.level),
+        content: clean(logging, entry.content, true),
+        tags: entry.tags,
+        service: field(message.and_then(|m| m.service.as_ref())),
+        host: field(message.and_then(|m| m.host.as_ref())),
+        trace_id: field(message.and_then(|m| m.trace_id.as_ref())),
+        span_id: field(message.and_then(|m| m.span_id.as_ref())),
+        source_timestamp: entry.source_timestamp.map(|value| clean(logging, value, true)),
+        fields: message
+            .filter(|m| !m.extra.is_empty())
+            .map(|m| clean_fields(logging, &m.extra)),
+        kafka: entry.record.map(|record| JsonKafka {
+            topic: &record.topic,
+            partition: record.partition,
+            offset: record.offset,
+            key: field(record.key.as_ref()),
+            headers: record
+                .headers
+                .iter()
+                .map(|(k, v)| (clean(logging, k, true), clean(logging, v, true)))
+                .collect(),
+        }),
+    };
+
+    let mut json = serde_json::to_string(&line).map_err(|e| format!("JSON行序列化失败: {}", e))?;
+    json.push('\n');
+    Ok(json)
+}
+
+fn clean<'a>(logging: &LoggingConfig, value: &'a str, keep_newlines: bool) -> Cow<'a, str> {
+    if logging.sanitize {
+        sanitize::sanitize_content(value, keep_newlines)
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+// 结构化字段中的字符串（含键名）同样清洗，JSON转义不处理C1控制字符、DEL和ANSI序列
+fn clean_fields<'a>(
+    logging: &LoggingConfig,
+    fields: &'a serde_json::Map<String, serde_json::Value>,
+) -> Cow<'a, serde_json::Map<String, serde_json::Value>> {
+    if !logging.sanitize {
+        return Cow::Borrowed(fields);
+    }
+    Cow::Owned(clean_object(fields))
+}
+
+fn clean_object(fields: &serde_json::Map<String, serde_json::Value>) -> serde_json::Map<String, serde_json::Value> {
+    fields
+        .iter()
+        .map(|(key, value)| (sanitize::sanitize_content(key, true).into_owned(), clean_value(value)))
+        .collect()
+}
+
+fn clean_value(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) => serde_json::Value::String(sanitize::sanitize_content(s, true).into_owned()),
+        serde_json::Value::Array(items) => serde_json::Value::Array(items.iter().map(clean_value).collect()),
+        serde_json::Value::Object(object) => serde_json::Value::Object(clean_object(object)),
+        other => other.clone(),
+    }
+}
+
+// 按 enrichment.format 渲染Kafka元数据，仅Kafka来源的条目有元数据
+fn metadata(logging: &LoggingConfig, entry: &LogEntry) -> Option<String> {
+    Some(render_record_metadata(
+        entry.record?,
+        entry.message?,
+        &logging.enrichment,
+    ))
+}
+
+// 规范化的完整级别名，未知级别按INFO处理
+fn level_name(level: &str) -> &'static str {
+    LogLevel::from_str(level).map_or(crate::LEVEL_INFO, |level| level.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, logging};
+
+    fn render(logging: &LoggingConfig, output: &OutputConfig, record: &KafkaRecord, message: &KafkaMessage) -> String {
+        let time = DateTime::parse_from_rfc3339("2026-10-18T12:00:00Z").unwrap();
+        let entry = LogEntry {
+            time: &time,
+            timestamp: "2026-10-18 12:00:00",
+            source_timestamp: Some("\u{1b}[2J"),
+            level: &message.l,
+            content: &message.s,
+            tags: &[],
+            record: Some(record),
+            message: Some(message),
+        };
+        render_line(logging, output, &entry).unwrap()
+    }
+
+    fn record() -> KafkaRecord {
+        let mut record = testutil::record("app", 0, 1, b"");
+        record.key = Some("k\u{9b}31m".to_string());
+        record.headers = vec![("h\u{7f}".to_string(), "\u{1b}]0;title\u{07}".to_string())];
+        record
+    }
+
+    #[test]
+    fn framed_content_round_trips_through_sanitize() {
+        let logging = logging("{ sanitize: true, multiline: { policy: framed } }");
+        let original = "ok\n[2026-10-18 12:00:00] [F] forged\nend";
+        let message = KafkaMessage::new("INFO".to_string(), original.to_string());
+        let line = render(&logging, &logging.output, &record(), &message);
+
+        // 换行由长度前缀处理，读回时伪造的续行仍属于这条记录
+        let records = format::parse_records(&line, &logging.multiline);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].abbreviation, "I");
+        assert_eq!(records[0].content, original);
+
+        // 其他控制字符照常转义
+        let message = KafkaMessage::new("INFO".to_string(), "a\u{1b}[2Jb\nc".to_string());
+        let line = render(&logging, &logging.output, &record(), &message);
+        assert_eq!(format::parse_records(&line, &logging.multiline)[0].content, "a\\x1b[2Jb\nc");
+    }
+
+    #[test]
+    fn json_fields_are_sanitized() {
+        let logging = logging("{}");
+        let output = OutputConfig {
+            format: OUTPUT_JSON.to_string(),
+            template: default_output_template(),
+        };
+        let mut message = KafkaMessage::new("INFO".to_string(), "ok".to_string());
+        message.extra.insert("user\u{1b}".to_string(), serde_json::json!({"name": ["\u{9b}1m\u{85}"]}));
+        let line = render(&logging, &output, &record(), &message);
+
+        assert!(!line.trim_end().chars().any(|c| c.is_control()), "{}", line);
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["source_timestamp"], "\\x1b[2J");
+        assert_eq!(json["kafka"]["key"], "k\\u{009b}31m");
+        assert_eq!(json["kafka"]["headers"]["h\\x7f"], "\\x1b]0;title\\x07");
+        assert_eq!(json["fields"]["user\\x1b"]["name"][0], "\\u{009b}1m\\u{0085}");
+    }
+}
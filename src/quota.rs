@@ -0,0 +1,334 @@
+// 按容量清理：总容量上限（max_total_size）和可选的单服务配额（service_quota）
+//
+// 超出上限时按保留规则最先到期的顺序删除文件（保留期短的级别/路由先删，同样的保留期从旧到新），
+// 直到降到低水位（上限的 low_water_percent%）。删除与过期清理经过同一流程，启用归档时先归档再删除。
+// 每天的定时清理之后执行一次；写入方累计写入字节数，超过上次扫描时剩余的空间后
+// 通知后台任务立即执行，不必等到下一次定时清理。正在写入的时间段不会被删除。
+//
+// 服务按布局中的 {service} 区分，布局没有 {service} 时按路由目录区分。
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::NaiveDateTime;
+use tokio::sync::Notify;
+
+use crate::{archive::ArchiveConfig, cleanup, clock::Clock, layout, retention::Retention, schedule, LoggingConfig};
+
+const DEFAULT_LOW_WATER_PERCENT: u64 = 90;
+// 已无可删除的文件时，至少再写入这么多字节后才重新扫描，避免每次写入都触发
+const MIN_RECHECK_BYTES: u64 = 16 << 20;
+
+const LOW_WATER_ERROR: &str = "low_water_percent 必须在1-100之间";
+
+// 上次扫描后还能写入的字节数，以及之后已写入的字节数
+static HEADROOM: AtomicU64 = AtomicU64::new(u64::MAX);
+static WRITTEN: AtomicU64 = AtomicU64::new(0);
+static WATERMARK: Notify = Notify::const_new();
+
+pub(crate) fn default_low_water_percent() -> u64 {
+    DEFAULT_LOW_WATER_PERCENT
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Quota {
+    max_total: Option<u64>,
+    per_service: Option<u64>,
+    low_water_percent: u64,
+}
+
+impl Quota {
+    // 未配置任何上限时返回None
+    pub(crate) fn new(config: &LoggingConfig) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if !(1..=100).contains(&config.low_water_percent) {
+            return Err(LOW_WATER_ERROR.into());
+        }
+
+        let max_total = match config.max_total_size {
+            Some(ref size) => Some(parse_size(size).map_err(|e| format!("max_total_size: {}", e))?),
+            None => None,
+        };
+        let per_service = match config.service_quota {
+            Some(ref size) => Some(parse_size(size).map_err(|e| format!("service_quota: {}", e))?),
+            None => None,
+        };
+        if max_total.is_none() && per_service.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Quota {
+            max_total,
+            per_service,
+            low_water_percent: config.low_water_percent,
+        }))
+    }
+
+    fn low_water(&self, limit: u64) -> u64 {
+        limit / 100 * self.low_water_percent + limit % 100 * self.low_water_percent / 100
+    }
+}
+
+// 解析容量，如 "500MB"、"10GB"、"1.5TB"、"4096"（字节），按1024换算
+pub(crate) fn parse_size(value: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("无效的容量: {}（示例: 500MB、10GB）", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("无效的容量单位: {}（支持 B、KB、MB、GB、TB）", value).into()),
+    };
+
+    let bytes = (number * multiplier as f64) as u64;
+    if bytes == 0 {
+        return Err(format!("容量必须大于0: {}", value).into());
+    }
+    Ok(bytes)
+}
+
+// 写入方在每次写入后调用，越过水位时唤醒按需清理
+pub(crate) fn record_written(bytes: usize) {
+    let written = WRITTEN.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+    if written > HEADROOM.load(Ordering::Relaxed) {
+        WATERMARK.notify_one();
+    }
+}
+
+// 按需清理任务：启动时执行一次以确定水位，之后每次越过水位时执行
+pub(crate) async fn run_on_demand(
+    log_path: String,
+    clock: Clock,
+    layout: layout::Layout,
+    quota: Quota,
+    retention: Retention,
+    archive: ArchiveConfig,
+) {
+    loop {
+        enforce_exclusive(&log_path, &clock, &layout, &quota, &retention, &archive).await;
+        WATERMARK.notified().await;
+        tklog::async_info!("cleanup|", "日志容量越过水位，开始按容量清理");
+    }
+}
+
+// 按需清理先取得目录树任务锁，与定时的清理、压缩和完整性检查不同时执行
+async fn enforce_exclusive(
+    log_path: &str,
+    clock: &Clock,
+    layout: &layout::Layout,
+    quota: &Quota,
+    retention: &Retention,
+    archive: &ArchiveConfig,
+) -> Vec<cleanup::Removal> {
+    let _tree_jobs = schedule::tree_jobs_guard().await;
+    enforce(log_path, clock, layout, quota, retention, archive).await
+}
+
+// 按容量清理，按保留优先级删除文件直到低于低水位，返回删除的文件
+// 调用方需持有目录树任务锁（定时的 cleanup 任务本身已持有）
+pub(crate) async fn enforce(
+    log_path: &str,
+    clock: &Clock,
+    layout: &layout::Layout,
+    quota: &Quota,
+    retention: &Retention,
+    archive: &ArchiveConfig,
+) -> Vec<cleanup::Removal> {
+    let root = Path::new(log_path);
+    let files = layout.scan(root);
+    let selected = plan(&files, clock.now().naive_local(), quota, retention)
+        .into_iter()
+        .map(|(index, reason)| cleanup::Removal {
+            file: files[index].clone(),
+            reason,
+        })
+        .collect();
+    let (removals, _) = cleanup::remove_files(root, archive, clock, selected).await;
+
+    // 剩余空间取总容量和各服务配额中最小的一个
+    let deleted: HashSet<&Path> = removals.iter().map(|r| r.file.path.as_path()).collect();
+    let mut total = 0u64;
+    let mut per_service: HashMap<String, u64> = HashMap::new();
+    for file in files.iter().filter(|file| !deleted.contains(file.path.as_path())) {
+        total += file.size;
+        *per_service.entry(file.service()).or_insert(0) += file.size;
+    }
+    let mut headroom = quota.max_total.map_or(u64::MAX, |limit| limit.saturating_sub(total));
+    if let Some(limit) = quota.per_service {
+        for usage in per_service.values() {
+            headroom = headroom.min(limit.saturating_sub(*usage));
+        }
+    }
+    HEADROOM.store(headroom.max(MIN_RECHECK_BYTES), Ordering::Relaxed);
+    WRITTEN.store(0, Ordering::Relaxed);
+
+    if !removals.is_empty() {
+        let deleted_bytes: u64 = removals.iter().map(|r| r.file.size).sum();
+        tklog::async_info!(
+            "cleanup|",
+            &format!(
+                "按容量清理完成，删除了{}个文件，释放 {} 字节，当前总量 {} 字节",
+                removals.len(), deleted_bytes, total
+            )
+        );
+    }
+    removals
+}
+
+// 选出需要删除的文件（files 中的下标）及原因，按保留规则最先到期的在前
+// 定时清理和 cleanup --dry-run 共用
+pub(crate) fn plan(
+    files: &[layout::LayoutFile],
+    now: NaiveDateTime,
+    quota: &Quota,
+    retention: &Retention,
+) -> Vec<(usize, String)> {
+    let order = by_expiry(files, retention);
+
+    let mut total: u64 = files.iter().map(|f| f.size).sum();
+    let mut per_service: HashMap<String, u64> = HashMap::new();
+    for file in files {
+        *per_service.entry(file.service()).or_insert(0) += file.size;
+    }
+    let mut selected = Vec::new();
+    let mut deleted = vec![false; files.len()];
+
+    // 先处理单服务配额，再处理总容量
+    if let Some(limit) = quota.per_service {
+        let target = quota.low_water(limit);
+        // 超出配额的服务一直删到低水位
+        let over: HashSet<String> = per_service
+            .iter()
+            .filter(|(_, usage)| **usage > limit)
+            .map(|(service, _)| service.clone())
+            .collect();
+        for &index in &order {
+            let file = &files[index];
+            let service = file.service();
+            if !over.contains(&service) {
+                continue;
+            }
+            let usage = per_service.get(&service).copied().unwrap_or(0);
+            if usage <= target || file.end > now {
+                continue; // 已降到低水位，或是正在写入的时间段
+            }
+            selected.push((index, format!("服务 {} 超出配额", display_service(&service))));
+            deleted[index] = true;
+            total -= file.size;
+            *per_service.entry(service).or_insert(0) -= file.size;
+        }
+    }
+
+    if let Some(limit) = quota.max_total {
+        if total > limit {
+            let target = quota.low_water(limit);
+            for &index in &order {
+                if total <= target {
+                    break;
+                }
+                let file = &files[index];
+                if deleted[index] || file.end > now {
+                    continue;
+                }
+                selected.push((index, "总容量超出上限".to_string()));
+                deleted[index] = true;
+                total -= file.size;
+            }
+        }
+    }
+
+    selected
+}
+
+// 文件下标按保留规则的到期时间排序，到期时间相同时从旧到新
+pub(crate) fn by_expiry(files: &[layout::LayoutFile], retention: &Retention) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..files.len()).collect();
+    order.sort_by_cached_key(|&index| {
+        let file = &files[index];
+        (retention.expires_at(file), file.start, file.path.clone())
+    });
+    order
+}
+
+pub(crate) fn display_service(service: &str) -> &str {
+    if service.is_empty() {
+        "(根目录)"
+    } else {
+        service
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn segment(day: u32, level: &str) -> layout::LayoutFile {
+        let start = chrono::NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        layout::LayoutFile {
+            path: format!("2026/10/{:02}/00.{}.log", day, level).into(),
+            start,
+            end: start + chrono::Duration::hours(1),
+            size: 100,
+            fields: HashMap::from([("level".to_string(), level.to_string())]),
+            compressed: false,
+        }
+    }
+
+    #[test]
+    fn plan_deletes_by_retention_priority() {
+        let logging = testutil::logging(
+            r#"
+retention_days: 30
+layout: "%Y/%m/%d/%H.{level}.log"
+retention:
+  - { levels: ["ERROR"], days: 365 }
+  - { levels: ["DEBUG"], days: 3 }
+max_total_size: "300"
+low_water_percent: 50
+"#,
+        );
+        let layout = layout::Layout::parse(&logging.layout).unwrap();
+        let retention = Retention::new(&logging, &layout).unwrap();
+        let quota = Quota::new(&logging).unwrap().unwrap();
+
+        // 最旧的是ERROR，但DEBUG的保留期最短，先于它删除
+        let files = vec![segment(1, "ERROR"), segment(2, "INFO"), segment(3, "DEBUG"), segment(4, "ERROR")];
+        let now = chrono::NaiveDate::from_ymd_opt(2026, 10, 5).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let selected: Vec<usize> = plan(&files, now, &quota, &retention).into_iter().map(|(i, _)| i).collect();
+        assert_eq!(selected, vec![2, 1, 0]);
+    }
+
+    #[tokio::test]
+    async fn on_demand_cleanup_waits_for_tree_jobs() {
+        testutil::silence_logging();
+        let temp = testutil::TempDir::new("quota_lock");
+        let logging = testutil::logging(&format!("path: {:?}\nmax_total_size: \"1GB\"", temp.path()));
+        let layout = layout::Layout::parse(&logging.layout).unwrap();
+        let retention = Retention::new(&logging, &layout).unwrap();
+        let quota = Quota::new(&logging).unwrap().unwrap();
+        let clock = Clock::new(&logging.time).unwrap();
+
+        // 定时任务执行期间按需清理只能等待
+        let guard = schedule::tree_jobs_guard().await;
+        let handle = tokio::spawn(async move {
+            enforce_exclusive(&logging.path, &clock, &layout, &quota, &retention, &logging.archive).await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!handle.is_finished());
+
+        drop(guard);
+        assert!(handle.await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+// 启动恢复：进程在写入中途退出时，日志文件末尾可能留下不完整的记录
+//
+// 启动时（写入初始化日志之前）检查最近 window_hours 小时内的时间段文件，
+// 找到最后一条完整记录的结尾，之后的部分按 action 处理：
+//   truncate   - 直接截断（默认）
+//   quarantine - 先把不完整的部分保存到 quarantine_path 下（原相对路径.偏移量.partial），再截断
+// framed策略从开头逐块读取并按长度前缀检查，内容恰好截断在其中的换行处也能发现；其他策略和JSON行从末尾按换行判断。
+// 被截断的记录对应的偏移量尚未提交，重启后会从Kafka重新投递。
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Duration as ChronoDuration;
+
+use crate::{clock::Clock, format, layout, output, LoggingConfig};
+
+const ACTION_TRUNCATE: &str = "truncate";
+const ACTION_QUARANTINE: &str = "quarantine";
+const DEFAULT_QUARANTINE_PATH: &str = "quarantine";
+const QUARANTINE_SUFFIX: &str = ".partial";
+// 每次读取的字节数：按换行判断时从末尾向前读取，framed策略从开头逐块读取
+const READ_CHUNK: u64 = 64 * 1024;
+
+const RECOVERY_ACTION_ERROR: &str = "recovery.action 只能是 truncate 或 quarantine";
+const RECOVERY_WINDOW_ERROR: &str = "recovery.window_hours 必须大于0";
+const RECOVERY_PATH_ERROR: &str = "recovery.quarantine_path 不能为空，且不能位于日志目录内";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct RecoveryConfig {
+    #[serde(default = "default_enabled")]
+    pub(crate) enabled: bool,
+    #[serde(default = "default_window_hours")]
+    pub(crate) window_hours: u64, // 检查最近多少小时内的时间段
+    #[serde(default = "default_action")]
+    pub(crate) action: String,
+    #[serde(default = "default_quarantine_path")]
+    pub(crate) quarantine_path: String, // quarantine 时保存不完整记录的目录
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> Self {
+        RecoveryConfig {
+            enabled: default_enabled(),
+            window_hours: default_window_hours(),
+            action: default_action(),
+            quarantine_path: default_quarantine_path(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_window_hours() -> u64 {
+    24
+}
+
+fn default_action() -> String {
+    ACTION_TRUNCATE.to_string()
+}
+
+fn default_quarantine_path() -> String {
+    DEFAULT_QUARANTINE_PATH.to_string()
+}
+
+pub(crate) fn validate_recovery(config: &RecoveryConfig, log_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if !config.enabled {
+        return Ok(());
+    }
+    if config.window_hours == 0 {
+        return Err(RECOVERY_WINDOW_ERROR.into());
+    }
+    match config.action.as_str() {
+        ACTION_TRUNCATE => Ok(()),
+        ACTION_QUARANTINE
+            if config.quarantine_path.trim().is_empty()
+                || layout::is_within(Path::new(&config.quarantine_path), Path::new(log_path)) =>
+        {
+            Err(RECOVERY_PATH_ERROR.into())
+        }
+        ACTION_QUARANTINE => Ok(()),
+        _ => Err(RECOVERY_ACTION_ERROR.into()),
+    }
+}
+
+// 一次修复：截断到的长度、去掉的字节数，以及隔离文件的位置
+struct Repair {
+    offset: u64,
+    removed: u64,
+    quarantined: Option<PathBuf>,
+}
+
+// 检查日志目录（以及spool目录）中最近的时间段文件，修复末尾不完整的记录
+pub(crate) async fn recover(logging: &LoggingConfig, clock: &Clock, layout: &layout::Layout) {
+    let config = &logging.recovery;
+    if !config.enabled {
+        return;
+    }
+    let since = clock.now().naive_local() - ChronoDuration::hours(config.window_hours as i64);
+
+    let mut checked = 0;
+    let mut repaired = 0;
+    for root in logging.roots() {
+        let root = Path::new(root);
+        for file in layout.scan(root) {
+            if file.compressed || file.end <= since {
+                continue;
+            }
+            checked += 1;
+            let route = file.fields.get("route").map(String::as_str);
+            let framed = !output::output_for(logging, route).is_json() && format::is_framed(&logging.multiline);
+
+            match repair_file(config, root, &file, framed) {
+                Ok(None) => {}
+                Ok(Some(repair)) => {
+                    repaired += 1;
+                    let action = match repair.quarantined {
+                        Some(ref target) => format!("已保存到 {:?} 并截断", target),
+                        None => "已截断".to_string(),
+                    };
+                    tklog::async_warn!(
+                        "recovery|",
+                        &format!(
+                            "{:?} 末尾有 {} 字节不完整的记录，{}到 {} 字节",
+                            file.path, repair.removed, action, repair.offset
+                        )
+                    );
+                }
+                Err(e) => {
+                    tklog::async_error!("recovery|", &format!("检查文件失败 {:?}: {}", file.path, e));
+                }
+            }
+        }
+    }
+
+    tklog::async_info!(
+        "recovery|",
+        &format!("启动恢复检查完成，检查了{}个最近的文件，修复了{}个", checked, repaired)
+    );
+}
+
+fn repair_file(
+    config: &RecoveryConfig,
+    root: &Path,
+    file: &layout::LayoutFile,
+    framed: bool,
+) -> io::Result<Option<Repair>> {
+    // 与压缩任务互斥
+    let _guard = layout::maintenance_guard();
+    let mut handle = OpenOptions::new().read(true).write(true).open(&file.path)?;
+    let size = handle.metadata()?.len();
+    let offset = complete_offset(&mut handle, size, framed)?;
+    if offset == size {
+        return Ok(None);
+    }
+
+    let quarantined = if config.action == ACTION_QUARANTINE {
+        let relative = file.path.strip_prefix(root).unwrap_or(&file.path);
+        let mut name = Path::new(&config.quarantine_path).join(relative).into_os_string();
+        name.push(format!(".{}{}", offset, QUARANTINE_SUFFIX));
+        let target = PathBuf::from(name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut tail = Vec::new();
+        handle.seek(SeekFrom::Start(offset))?;
+        handle.read_to_end(&mut tail)?;
+        let mut output = File::create(&target)?;
+        output.write_all(&tail)?;
+        output.sync_all()?;
+        Some(target)
+    } else {
+        None
+    };
+
+    handle.set_len(offset)?;
+    handle.sync_all()?;
+    Ok(Some(Repair {
+        offset,
+        removed: size - offset,
+        quarantined,
+    }))
+}
+
+// 最后一条完整记录的结尾位置
+fn complete_offset(file: &mut File, size: u64, framed: bool) -> io::Result<u64> {
+    if framed {
+        // 需要从文件开头按长度前缀逐条解析；逐块读取，只保留最后一条完整记录之后的部分
+        let mut complete = 0u64;
+        let mut pending = Vec::new();
+        let mut chunk = vec![0u8; READ_CHUNK as usize];
+        file.seek(SeekFrom::Start(0))?;
+        loop {
+            let read = file.read(&mut chunk)?;
+            if read == 0 {
+                return Ok(complete);
+            }
+            pending.extend_from_slice(&chunk[..read]);
+            let len = format::complete_len(&pending, true);
+            complete += len as u64;
+            pending.drain(..len);
+        }
+    }
+
+    // 从末尾向前找最后一个换行
+    let mut end = size;
+    while end > 0 {
+        let start = end.saturating_sub(READ_CHUNK);
+        let mut chunk = vec![0u8; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        let len = format::complete_len(&chunk, false);
+        if len > 0 {
+            return Ok(start + len as u64);
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn framed_scan_streams_across_chunks() {
+        let config = format::MultilineConfig {
+            policy: "framed".to_string(),
+            ..Default::default()
+        };
+        // 每条记录约3KB且含换行，整个文件跨越多个读取块
+        let body = "x".repeat(1000) + "\n[2026-10-18 12:00:00] [I] fake\n" + &"y".repeat(2000);
+        let mut data = String::new();
+        for _ in 0..100 {
+            data.push_str(&format::format_line("2026-10-18 12:00:00", "I", &body, &config));
+        }
+        let complete = data.len() as u64;
+        assert!(complete > 3 * READ_CHUNK);
+        let torn = format::format_line("2026-10-18 12:00:01", "E", &body, &config);
+        data.push_str(&torn[..torn.find("fake\n").unwrap() + 5]);
+
+        let temp = testutil::TempDir::new("recovery");
+        let path = temp.join("00.log");
+        fs::write(&path, &data).unwrap();
+        let mut file = File::open(&path).unwrap();
+        let size = data.len() as u64;
+        assert_eq!(complete_offset(&mut file, size, true).unwrap(), complete);
+        assert_eq!(complete_offset(&mut file, size, false).unwrap(), size);
+    }
+}
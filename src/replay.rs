@@ -0,0 +1,394 @@
+// 重放模式：按时间戳或偏移量区间重新消费主题，写入日志目录树
+//
+// 用法: log_server replay --from 2026-10-01T00:00 [--to 2026-10-02T00:00] [--output 目录] [--topic 主题]
+//       log_server replay --offsets 1000-2000 [--partition 分区] [--from ...] [--to ...]
+//
+// 重放使用独立的会话直接按分区拉取，不加入消费组，也从不提交偏移量，
+// 因此不会影响主消费组已提交的偏移量。
+//
+// 起止偏移量都由 ListOffsets 按时间戳查询：--to 对应的偏移量（没有更晚的记录时为分区末尾）为结束位置。
+// 生产者设置的 CreateTime 不保证随偏移量递增，区间内时间戳不在 [from, to) 的记录逐条跳过。
+// --offsets 起始-结束（不含结束，省略结束时到分区末尾）应用于所选的每个分区，同时指定时间时取交集。
+//
+// 重放不按偏移量检查点跳过记录，也不写检查点；指定 --output 时死信和spool目录同样放在输出目录下
+// （_dead_letter、_spool），不会混入主服务的目录。
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::{
+    clock::Clock, create_kafka_consumer, disk, fetch_kafka_records, list_offsets_by_timestamp,
+    process_kafka_message, Config, Pipeline, EMPTY_BROKERS_ERROR, EMPTY_TOPICS_ERROR,
+};
+
+// ListOffsets 的特殊时间戳：查询分区末尾（下一条记录的偏移量）
+const LATEST_TIMESTAMP: i64 = -1;
+// ListOffsets 没有时间戳不早于查询时间的记录时返回的偏移量
+const NO_OFFSET: i64 = -1;
+// --output 下的死信和spool目录
+const OUTPUT_DEAD_LETTER_DIR: &str = "_dead_letter";
+const OUTPUT_SPOOL_DIR: &str = "_spool";
+
+const REPLAY_USAGE: &str = "用法: log_server replay --from <时间> [--to <时间>] [--offsets <起始>-[结束]] \
+[--partition <分区>]... [--output <目录>] [--topic <主题>]...（--from 和 --offsets 至少指定一个）";
+
+#[derive(Debug)]
+struct ReplayOptions {
+    from: Option<DateTime<FixedOffset>>,
+    to: Option<DateTime<FixedOffset>>,
+    offsets: Option<OffsetRange>,
+    partitions: Vec<i32>, // 为空时重放所有分区
+    output: Option<String>,
+    topics: Vec<String>,
+}
+
+// 偏移量区间 [start, end)，end 为 None 时到分区末尾
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct OffsetRange {
+    start: i64,
+    end: Option<i64>,
+}
+
+pub(crate) async fn run_replay(
+    config: &Config,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    // 时间参数按配置的时区解释
+    let mut pipeline = Pipeline::new(config)?;
+    let options = parse_replay_args(args, &pipeline.clock)?;
+
+    // 重放不依赖消费组，只需要broker与主题
+    let topics = if options.topics.is_empty() {
+        config.kafka.topics.clone()
+    } else {
+        options.topics.clone()
+    };
+    if config.kafka.brokers.is_empty() {
+        return Err(EMPTY_BROKERS_ERROR.into());
+    }
+    if topics.is_empty() {
+        return Err(EMPTY_TOPICS_ERROR.into());
+    }
+
+    redirect_outputs(&mut pipeline, options.output.as_deref())?;
+
+    tklog::async_info!(
+        "replay|",
+        &format!(
+            "开始重放: {} ~ {}，偏移量: {}，主题: {:?}，输出目录: {}",
+            options
+                .from
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| "不限".to_string()),
+            options
+                .to
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| "最新".to_string()),
+            options
+                .offsets
+                .map(|range| format!("{}-{}", range.start, range.end.map(|e| e.to_string()).unwrap_or_default()))
+                .unwrap_or_else(|| "不限".to_string()),
+            topics,
+            pipeline.logging.path
+        )
+    );
+
+    let consumer_addresses = create_kafka_consumer(&config.kafka)?;
+    let from_ms = options.from.map(|t| t.timestamp_millis());
+    let to_ms = options.to.map(|t| t.timestamp_millis());
+
+    let mut replayed_count = 0u64;
+    let mut failed_count = 0u64;
+    let mut failed_entry_count = 0u64;
+    let mut skipped_count = 0u64;
+
+    for topic in &topics {
+        // 各分区按时间戳查到的起始偏移量；只按偏移量重放时从分区列表开始
+        let time_starts = match from_ms {
+            Some(from) => list_offsets_by_timestamp(&consumer_addresses, topic, from).await?,
+            None => list_offsets_by_timestamp(&consumer_addresses, topic, LATEST_TIMESTAMP)
+                .await?
+                .into_iter()
+                .map(|(partition, _)| (partition, 0))
+                .collect(),
+        };
+        let time_starts: Vec<(i32, i64)> = time_starts
+            .into_iter()
+            .filter(|(partition, _)| options.partitions.is_empty() || options.partitions.contains(partition))
+            .collect();
+        if time_starts.is_empty() {
+            tklog::async_warn!("replay|", &format!("主题 {} 没有可重放的分区", topic));
+            continue;
+        }
+        let end_offsets = match to_ms {
+            Some(to) => Some(end_offsets(&consumer_addresses, topic, to).await?),
+            None => None,
+        };
+
+        for (partition, time_start) in time_starts {
+            if time_start == NO_OFFSET {
+                tklog::async_info!(
+                    "replay|",
+                    &format!("分区 {}-{} 没有不早于起始时间的记录，跳过", topic, partition)
+                );
+                continue;
+            }
+            // 没有 --to 和区间结束时重放到分区末尾（拉取结果为空）
+            let time_end = end_offsets.as_ref().and_then(|ends| ends.get(&partition).copied());
+            let (start_offset, end_offset) = replay_range(time_start, time_end, options.offsets);
+            let mut offset = start_offset;
+
+            'partition: loop {
+                if end_offset.is_some_and(|end| offset >= end) {
+                    break;
+                }
+                let records =
+                    fetch_kafka_records(&consumer_addresses, topic, partition, offset).await?;
+                if records.is_empty() {
+                    break; // 已到达分区末尾
+                }
+
+                for record in records {
+                    if end_offset.is_some_and(|end| record.offset >= end) {
+                        break 'partition; // 到达结束偏移量
+                    }
+                    offset = record.offset + 1;
+                    // 时间戳不随偏移量递增时，区间内可能夹杂区间外的记录
+                    if from_ms.is_some_and(|from| record.timestamp_ms < from)
+                        || to_ms.is_some_and(|to| record.timestamp_ms >= to)
+                    {
+                        skipped_count += 1;
+                        continue;
+                    }
+
+                    // 重放以记录自身的时间戳决定写入的文件
+                    let log_time = pipeline.clock.at_millis(record.timestamp_ms);
+                    match process_kafka_message(&record, &pipeline, log_time).await {
+                        Ok(report) => {
+                            replayed_count += 1;
+                            failed_entry_count += report.failed as u64;
+                        }
+                        Err(e) => {
+                            failed_count += 1;
+                            tklog::async_error!(
+                                "replay|",
+                                &format!(
+                                    "重放记录失败 {}-{}@{}: {}",
+                                    record.topic, record.partition, record.offset, e
+                                )
+                            );
+                        }
+                    }
+                }
+            }
+
+            tklog::async_info!(
+                "replay|",
+                &format!("分区 {}-{} 重放完成，结束偏移量: {}", topic, partition, offset)
+            );
+        }
+    }
+
+    // 重放不提交偏移量，结束前按落盘策略同步写过的文件
+    if let Err(e) = pipeline.durability.sync() {
+        tklog::async_error!("replay|", &format!("日志落盘失败: {}", e));
+    }
+
+    tklog::async_info!(
+        "replay|",
+        &format!(
+            "重放结束，成功 {} 条，失败 {} 条，解码失败条目 {} 个，时间戳不在区间内跳过 {} 条",
+            replayed_count, failed_count, failed_entry_count, skipped_count
+        )
+    );
+
+    Ok(())
+}
+
+// 重放不使用偏移量检查点；指定输出目录时，日志、死信和spool都写到输出目录下
+fn redirect_outputs(pipeline: &mut Pipeline, output: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    pipeline.logging.idempotent = false;
+    let Some(output) = output else {
+        return Ok(());
+    };
+
+    let output = Path::new(output);
+    pipeline.logging.path = output.to_string_lossy().into_owned();
+    pipeline.limits.dead_letter_path = output.join(OUTPUT_DEAD_LETTER_DIR).to_string_lossy().into_owned();
+    if pipeline.logging.spool_path().is_some() {
+        pipeline.logging.disk_guard.spool_path = Some(output.join(OUTPUT_SPOOL_DIR).to_string_lossy().into_owned());
+        pipeline.disk = disk::DiskGuard::new(&pipeline.logging.disk_guard)?;
+    }
+    Ok(())
+}
+
+// 分区的重放区间 [起始, 结束)：按时间戳查到的区间与 --offsets 取交集，结束为 None 表示到分区末尾
+fn replay_range(time_start: i64, time_end: Option<i64>, offsets: Option<OffsetRange>) -> (i64, Option<i64>) {
+    let Some(range) = offsets else {
+        return (time_start, time_end);
+    };
+    let end = match (time_end, range.end) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    (time_start.max(range.start), end)
+}
+
+// 各分区的结束偏移量：--to 对应的偏移量，没有更晚的记录时为分区末尾
+async fn end_offsets(
+    consumer: &[SocketAddr],
+    topic: &str,
+    to_ms: i64,
+) -> Result<HashMap<i32, i64>, Box<dyn std::error::Error>> {
+    let mut ends: HashMap<i32, i64> = list_offsets_by_timestamp(consumer, topic, to_ms)
+        .await?
+        .into_iter()
+        .filter(|&(_, offset)| offset != NO_OFFSET)
+        .collect();
+    for (partition, latest) in list_offsets_by_timestamp(consumer, topic, LATEST_TIMESTAMP).await? {
+        ends.entry(partition).or_insert(latest);
+    }
+    Ok(ends)
+}
+
+fn parse_replay_args(args: &[String], clock: &Clock) -> Result<ReplayOptions, Box<dyn std::error::Error>> {
+    let mut from = None;
+    let mut to = None;
+    let mut offsets = None;
+    let mut partitions = Vec::new();
+    let mut output = None;
+    let mut topics = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| format!("参数 {} 缺少取值\n{}", arg, REPLAY_USAGE))
+        };
+        match arg.as_str() {
+            "--from" => from = Some(parse_replay_time(&value()?, clock)?),
+            "--to" => to = Some(parse_replay_time(&value()?, clock)?),
+            "--offsets" => offsets = Some(parse_offset_range(&value()?)?),
+            "--partition" => {
+                let partition = value()?;
+                partitions.push(partition.parse().map_err(|_| format!("无效的分区: {}", partition))?);
+            }
+            "--output" => output = Some(value()?),
+            "--topic" => topics.push(value()?),
+            _ => return Err(format!("未知参数: {}\n{}", arg, REPLAY_USAGE).into()),
+        }
+    }
+
+    if from.is_none() && offsets.is_none() {
+        return Err(format!("缺少 --from 或 --offsets 参数\n{}", REPLAY_USAGE).into());
+    }
+    if from.is_none() && to.is_some() {
+        return Err("--to 需要同时指定 --from".into());
+    }
+    if to.zip(from).is_some_and(|(to, from)| to <= from) {
+        return Err("--to 必须晚于 --from".into());
+    }
+
+    Ok(ReplayOptions {
+        from,
+        to,
+        offsets,
+        partitions,
+        output,
+        topics,
+    })
+}
+
+// 起始-结束，不含结束；省略结束时到分区末尾
+fn parse_offset_range(value: &str) -> Result<OffsetRange, Box<dyn std::error::Error>> {
+    let invalid = || format!("无效的偏移量区间: {}（示例: 1000-2000 或 1000-）", value);
+    let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+    let start: i64 = start.parse().map_err(|_| invalid())?;
+    let end = match end {
+        "" => None,
+        end => Some(end.parse::<i64>().map_err(|_| invalid())?),
+    };
+    if start < 0 || end.is_some_and(|end| end <= start) {
+        return Err(invalid().into());
+    }
+    Ok(OffsetRange { start, end })
+}
+
+fn parse_replay_time(
+    value: &str,
+    clock: &Clock,
+) -> Result<DateTime<FixedOffset>, Box<dyn std::error::Error>> {
+    clock
+        .parse(value)
+        .ok_or_else(|| format!("无效的时间: {}（示例: 2026-10-01T00:00）", value).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn offset_range_limits_each_partition() {
+        let clock = Clock::new(&testutil::logging("").time).unwrap();
+        let options = parse_replay_args(&args(&["--offsets", "100-200", "--partition", "2"]), &clock).unwrap();
+        assert_eq!(options.offsets, Some(OffsetRange { start: 100, end: Some(200) }));
+        assert_eq!(options.partitions, [2]);
+        assert!(options.from.is_none());
+
+        let open = parse_replay_args(&args(&["--offsets", "100-"]), &clock).unwrap();
+        assert_eq!(open.offsets, Some(OffsetRange { start: 100, end: None }));
+        for invalid in ["100", "200-100", "-5-10", "a-b"] {
+            assert!(parse_replay_args(&args(&["--offsets", invalid]), &clock).is_err(), "{}", invalid);
+        }
+        assert!(parse_replay_args(&args(&["--output", "rebuild"]), &clock).is_err());
+
+        // 只按偏移量：分区从0开始，到区间结束
+        assert_eq!(replay_range(0, None, options.offsets), (100, Some(200)));
+        // 与按时间戳查到的区间取交集
+        assert_eq!(replay_range(150, Some(400), options.offsets), (150, Some(200)));
+        assert_eq!(replay_range(50, Some(120), options.offsets), (100, Some(120)));
+        assert_eq!(replay_range(50, Some(120), open.offsets), (100, Some(120)));
+        assert_eq!(replay_range(50, None, open.offsets), (100, None));
+        // 没有偏移量区间时只按时间戳
+        assert_eq!(replay_range(50, Some(120), None), (50, Some(120)));
+    }
+
+    #[test]
+    fn output_takes_every_side_output() {
+        let logging = testutil::logging(
+            "{ path: logs, idempotent: true, disk_guard: { enabled: true, policy: spool, spool_path: spool } }",
+        );
+        let config = Config {
+            logging,
+            kafka: serde_yaml::from_str(
+                "{ enabled: true, brokers: [], group_id: g, topics: [], auto_offset_reset: latest, \
+                   session_timeout_ms: 1, heartbeat_interval_ms: 1, reconnect_interval_ms: 1 }",
+            )
+            .unwrap(),
+            rules: Vec::new(),
+            sources: HashMap::new(),
+            limits: serde_yaml::from_str("dead_letter_path: dead_letter").unwrap(),
+        };
+
+        // 不指定输出目录时只关闭检查点
+        let mut pipeline = Pipeline::new(&config).unwrap();
+        redirect_outputs(&mut pipeline, None).unwrap();
+        assert!(!pipeline.logging.idempotent);
+        assert_eq!(pipeline.logging.path, "logs");
+        assert_eq!(pipeline.limits.dead_letter_path, "dead_letter");
+
+        let mut pipeline = Pipeline::new(&config).unwrap();
+        redirect_outputs(&mut pipeline, Some("rebuild")).unwrap();
+        assert!(!pipeline.logging.idempotent);
+        assert_eq!(pipeline.logging.roots(), ["rebuild", "rebuild/_spool"]);
+        assert_eq!(pipeline.limits.dead_letter_path, "rebuild/_dead_letter");
+    }
+}
@@ -0,0 +1,172 @@
+// 按路由和级别的保留规则
+//
+// 规则按配置顺序匹配，第一条匹配的规则决定文件的保留天数，都不匹配时使用 retention_days。
+//   route  - 路由目录名（布局中的 {route}，或未写 {route} 时最前面的路由目录），不填表示任意路由
+//   levels - 日志级别列表，需要布局中包含 {level}（按级别拆分文件），不填表示任意级别
+//   days   - 保留天数
+// 例如错误日志保留一年、调试日志保留三天：
+//   layout: "%Y/%m/%d/%H.{level}.log"
+//   retention:
+//     - { levels: ["ERROR", "FATAL"], days: 365 }
+//     - { levels: ["TRACE", "DEBUG"], days: 3 }
+use chrono::{Duration as ChronoDuration, NaiveDateTime};
+
+use crate::{layout, LogLevel, LoggingConfig};
+
+const RULE_DAYS_ERROR: &str = "retention 规则的保留天数必须大于0";
+const RULE_EMPTY_ERROR: &str = "retention 规则至少需要 route 或 levels 之一";
+const RULE_LEVEL_LAYOUT_ERROR: &str = "retention 规则按级别保留时 logging.layout 必须包含 {level}";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct RetentionRule {
+    #[serde(default)]
+    pub(crate) route: Option<String>, // 路由目录名
+    #[serde(default)]
+    pub(crate) levels: Vec<String>, // 日志级别，如 ["ERROR", "FATAL"]
+    pub(crate) days: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    route: Option<String>,
+    levels: Vec<LogLevel>,
+    days: u32,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Retention {
+    default_days: u32,
+    rules: Vec<Rule>,
+}
+
+impl Retention {
+    pub(crate) fn new(config: &LoggingConfig, layout: &layout::Layout) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut rules = Vec::new();
+        for (index, rule) in config.retention.iter().enumerate() {
+            let context = |message: &str| format!("retention[{}]: {}", index, message);
+            if rule.days == 0 {
+                return Err(context(RULE_DAYS_ERROR).into());
+            }
+            if rule.route.is_none() && rule.levels.is_empty() {
+                return Err(context(RULE_EMPTY_ERROR).into());
+            }
+            if !rule.levels.is_empty() && !layout.has_field("level") {
+                return Err(context(RULE_LEVEL_LAYOUT_ERROR).into());
+            }
+
+            let levels = rule
+                .levels
+                .iter()
+                .map(|level| LogLevel::from_str(level).ok_or_else(|| context(&format!("无效的日志级别 {}", level))))
+                .collect::<Result<Vec<_>, _>>()?;
+            rules.push(Rule {
+                route: rule.route.clone(),
+                levels,
+                days: rule.days,
+            });
+        }
+
+        Ok(Retention {
+            default_days: config.retention_days,
+            rules,
+        })
+    }
+
+    // 文件适用的保留天数
+    pub(crate) fn days_for(&self, file: &layout::LayoutFile) -> u32 {
+        let route = file.fields.get("route");
+        let level = file.fields.get("level").and_then(|level| LogLevel::from_str(level));
+
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.route.as_ref().is_none_or(|r| Some(r) == route)
+                    && (rule.levels.is_empty() || level.as_ref().is_some_and(|l| rule.levels.contains(l)))
+            })
+            .map_or(self.default_days, |rule| rule.days)
+    }
+
+    // 文件按保留规则到期的时间，按容量删除时最先到期的文件优先
+    pub(crate) fn expires_at(&self, file: &layout::LayoutFile) -> NaiveDateTime {
+        file.end + ChronoDuration::days(self.days_for(file) as i64)
+    }
+
+    // 用于日志输出，如 "默认保留90天，另有2条按路由/级别的规则"
+    pub(crate) fn describe(&self) -> String {
+        if self.rules.is_empty() {
+            format!("保留{}天", self.default_days)
+        } else {
+            format!("默认保留{}天，另有{}条按路由/级别的规则", self.default_days, self.rules.len())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use std::collections::HashMap;
+
+    fn retention(rules: &str) -> Result<Retention, Box<dyn std::error::Error>> {
+        let logging = testutil::logging(&format!("{{ layout: \"%Y/%m/%d/%H.{{level}}.log\", retention: {} }}", rules));
+        let layout = layout::Layout::parse(&logging.layout).unwrap();
+        Retention::new(&logging, &layout)
+    }
+
+    fn file(route: Option<&str>, level: &str) -> layout::LayoutFile {
+        let start = chrono::NaiveDate::from_ymd_opt(2026, 10, 18).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let mut fields = HashMap::from([("level".to_string(), level.to_string())]);
+        if let Some(route) = route {
+            fields.insert("route".to_string(), route.to_string());
+        }
+        layout::LayoutFile {
+            path: "x.log".into(),
+            start,
+            end: start + ChronoDuration::hours(1),
+            size: 1,
+            fields,
+            compressed: false,
+        }
+    }
+
+    #[test]
+    fn days_for_takes_the_first_matching_rule() {
+        let retention = retention(
+            "[{ route: audit, levels: [ERROR], days: 365 }, { levels: [ERROR, FATAL], days: 90 }, \
+             { route: audit, days: 30 }, { levels: [DEBUG], days: 2 }]",
+        )
+        .unwrap();
+        let days = |route, level| retention.days_for(&file(route, level));
+
+        // 路由和级别都配置时两者都要满足
+        assert_eq!(days(Some("audit"), "ERROR"), 365);
+        assert_eq!(days(Some("audit"), "INFO"), 30);
+        assert_eq!(days(Some("api"), "ERROR"), 90);
+        assert_eq!(days(Some("api"), "FATAL"), 90);
+        // 按配置顺序，路由规则先于之后的级别规则
+        assert_eq!(days(Some("audit"), "DEBUG"), 30);
+        assert_eq!(days(Some("api"), "DEBUG"), 2);
+        // 都不匹配时使用 retention_days
+        assert_eq!(days(Some("api"), "INFO"), 7);
+        assert_eq!(days(None, "ERROR"), 90);
+        assert_eq!(days(None, "INFO"), 7);
+        // 无法识别的级别不匹配任何级别规则
+        assert_eq!(days(Some("api"), "bogus"), 7);
+        assert_eq!(days(Some("audit"), "bogus"), 30);
+
+        let expires = retention.expires_at(&file(Some("api"), "DEBUG"));
+        assert_eq!(expires, file(None, "").end + ChronoDuration::days(2));
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        for rules in ["[{ route: audit, days: 0 }]", "[{ days: 30 }]", "[{ levels: [LOUD], days: 30 }]"] {
+            assert!(retention(rules).is_err(), "{}", rules);
+        }
+
+        // 按级别保留需要布局中的 {level}
+        let logging = testutil::logging("{ retention: [{ levels: [ERROR], days: 30 }] }");
+        let layout = layout::Layout::parse(&logging.layout).unwrap();
+        assert!(Retention::new(&logging, &layout).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+// 规则引擎：在消息解析之后、写入日志之前，按顺序匹配规则并执行动作
+//
+// 每条规则包含匹配条件（级别、来源主题、内容正则、字段相等）和一个动作：
+//   route - 写入指定路由目录（终止后续规则）
+//   drop  - 丢弃该条消息（终止后续规则）
+//   tag   - 为该行添加标签
+//   copy  - 额外复制一份到指定路由目录
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use regex::Regex;
+
+use crate::{sanitize_route_name, KafkaMessage, LogLevel};
+
+const RULE_ACTION_ERROR: &str = "规则动作只能是 route、drop、tag 或 copy";
+const RULE_DESTINATION_ERROR: &str = "route/copy 规则必须配置合法的 destination（字母、数字、.、_、-）";
+const RULE_TAG_ERROR: &str = "tag 规则必须配置 tag";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct RuleConfig {
+    name: String,
+    #[serde(default, rename = "match")]
+    matcher: RuleMatchConfig,
+    action: String,
+    #[serde(default)]
+    destination: Option<String>, // route/copy 的目标路由目录
+    #[serde(default)]
+    tag: Option<String>,
+}
+
+// 所有已配置的条件都满足才算命中；未配置任何条件的规则匹配所有消息
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+struct RuleMatchConfig {
+    #[serde(default)]
+    levels: Vec<String>,
+    #[serde(default)]
+    sources: Vec<String>, // 来源主题
+    #[serde(default)]
+    content: Option<String>, // 内容正则
+    #[serde(default)]
+    fields: HashMap<String, String>, // 消息字段相等（通用字段或附加字段）
+}
+
+#[derive(Debug)]
+enum RuleAction {
+    Route(String),
+    Drop,
+    Tag(String),
+    Copy(String),
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    levels: Vec<LogLevel>,
+    sources: Vec<String>,
+    content: Option<Regex>,
+    fields: HashMap<String, String>,
+    action: RuleAction,
+    hits: AtomicU64,
+}
+
+// 规则执行结果
+#[derive(Debug, Default)]
+pub(crate) struct RuleOutcome {
+    pub(crate) drop: bool,
+    pub(crate) route: Option<String>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) copies: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub(crate) fn compile(configs: &[RuleConfig]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut rules = Vec::with_capacity(configs.len());
+
+        for config in configs {
+            let destination = || {
+                config
+                    .destination
+                    .as_deref()
+                    .filter(|d| sanitize_route_name(d).as_deref() == Some(*d))
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("规则 {}: {}", config.name, RULE_DESTINATION_ERROR))
+            };
+            let action = match config.action.as_str() {
+                "route" => RuleAction::Route(destination()?),
+                "copy" => RuleAction::Copy(destination()?),
+                "drop" => RuleAction::Drop,
+                "tag" => RuleAction::Tag(
+                    config
+                        .tag
+                        .clone()
+                        .filter(|t| !t.is_empty())
+                        .ok_or_else(|| format!("规则 {}: {}", config.name, RULE_TAG_ERROR))?,
+                ),
+                _ => return Err(format!("规则 {}: {}", config.name, RULE_ACTION_ERROR).into()),
+            };
+
+            let mut levels = Vec::with_capacity(config.matcher.levels.len());
+            for level in &config.matcher.levels {
+                levels.push(
+                    LogLevel::from_str(level)
+                        .ok_or_else(|| format!("规则 {}: 无效的日志级别 {}", config.name, level))?,
+                );
+            }
+
+            let content = match config.matcher.content {
+                Some(ref pattern) => Some(Regex::new(pattern).map_err(|e| {
+                    format!("规则 {}: 内容正则无效: {}", config.name, e)
+                })?),
+                None => None,
+            };
+
+            rules.push(Rule {
+                name: config.name.clone(),
+                levels,
+                sources: config.matcher.sources.clone(),
+                content,
+                fields: config.matcher.fields.clone(),
+                action,
+                hits: AtomicU64::new(0),
+            });
+        }
+
+        Ok(RuleSet { rules })
+    }
+
+    pub(crate) fn evaluate(&self, source: &str, message: &KafkaMessage) -> RuleOutcome {
+        let mut outcome = RuleOutcome::default();
+
+        for rule in &self.rules {
+            if !rule.matches(source, message) {
+                continue;
+            }
+            rule.hits.fetch_add(1, Ordering::Relaxed);
+
+            match rule.action {
+                RuleAction::Drop => {
+                    outcome.drop = true;
+                    break;
+                }
+                RuleAction::Route(ref destination) => {
+                    outcome.route = Some(destination.clone());
+                    break;
+                }
+                RuleAction::Tag(ref tag) => outcome.tags.push(tag.clone()),
+                RuleAction::Copy(ref destination) => outcome.copies.push(destination.clone()),
+            }
+        }
+
+        outcome
+    }
+
+    // 规则命中统计，格式: name=次数, ...
+    pub(crate) fn hit_summary(&self) -> String {
+        self.rules
+            .iter()
+            .map(|rule| format!("{}={}", rule.name, rule.hits.load(Ordering::Relaxed)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl Rule {
+    fn matches(&self, source: &str, message: &KafkaMessage) -> bool {
+        if !self.levels.is_empty()
+            && !LogLevel::from_str(&message.l).is_some_and(|level| self.levels.contains(&level))
+        {
+            return false;
+        }
+
+        if !self.sources.is_empty() && !self.sources.iter().any(|s| s == source) {
+            return false;
+        }
+
+        if let Some(ref content) = self.content {
+            if !content.is_match(&message.s) {
+                return false;
+            }
+        }
+
+        // 字段按字符串形式比较，支持通用字段、附加字段和 a.b 嵌套路径
+        self.fields
+            .iter()
+            .all(|(name, expected)| message.field(name).is_some_and(|value| value == *expected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(yaml: &str) -> RuleSet {
+        let configs: Vec<RuleConfig> = serde_yaml::from_str(yaml).unwrap();
+        RuleSet::compile(&configs).unwrap()
+    }
+
+    fn message(level: &str, content: &str) -> KafkaMessage {
+        KafkaMessage::new(level.to_string(), content.to_string())
+    }
+
+    #[test]
+    fn each_action_applies() {
+        let set = rules(
+            r#"
+- { name: noise, match: { content: "^healthcheck" }, action: drop }
+- { name: audit, match: { sources: [audit] }, action: route, destination: audit }
+- { name: errors, match: { levels: [ERROR, FATAL] }, action: tag, tag: alert }
+- { name: billing, match: { fields: { service: billing } }, action: copy, destination: billing }
+"#,
+        );
+
+        let outcome = set.evaluate("app", &message("INFO", "healthcheck ok"));
+        assert!(outcome.drop);
+
+        let outcome = set.evaluate("audit", &message("INFO", "login"));
+        assert!(!outcome.drop);
+        assert_eq!(outcome.route.as_deref(), Some("audit"));
+
+        let mut error = message("ERROR", "failed");
+        error.service = Some("billing".to_string());
+        let outcome = set.evaluate("app", &error);
+        assert_eq!(outcome.route, None);
+        assert_eq!(outcome.tags, ["alert"]);
+        assert_eq!(outcome.copies, ["billing"]);
+        assert_eq!(set.hit_summary(), "noise=1, audit=1, errors=1, billing=1");
+    }
+
+    #[test]
+    fn first_terminal_rule_wins() {
+        let set = rules(
+            r#"
+- { name: tag-all, action: tag, tag: seen }
+- { name: to-a, match: { levels: [WARN] }, action: route, destination: a }
+- { name: to-b, match: { levels: [WARN] }, action: route, destination: b }
+- { name: drop-warn, match: { levels: [WARN] }, action: drop }
+- { name: tag-late, action: tag, tag: late }
+"#,
+        );
+
+        // route 终止后续规则：第二条 route、drop 和之后的 tag 都不执行
+        let outcome = set.evaluate("app", &message("WARN", "slow"));
+        assert_eq!(outcome.route.as_deref(), Some("a"));
+        assert!(!outcome.drop);
+        assert_eq!(outcome.tags, ["seen"]);
+        assert_eq!(set.hit_summary(), "tag-all=1, to-a=1, to-b=0, drop-warn=0, tag-late=0");
+
+        // 不终止的规则按顺序累积
+        let outcome = set.evaluate("app", &message("INFO", "ok"));
+        assert_eq!(outcome.tags, ["seen", "late"]);
+    }
+
+    #[test]
+    fn non_matching_records_pass_through() {
+        let set = rules(
+            r#"
+- name: strict
+  match: { levels: [ERROR], sources: [app], content: "timeout", fields: { region: eu } }
+  action: drop
+"#,
+        );
+
+        // 所有条件都满足才命中
+        let mut matching = message("ERROR", "db timeout");
+        matching.extra.insert("region".to_string(), serde_json::json!("eu"));
+        assert!(set.evaluate("app", &matching).drop);
+
+        let mut wrong_field = message("ERROR", "db timeout");
+        wrong_field.extra.insert("region".to_string(), serde_json::json!("us"));
+        for (source, message) in [
+            ("app", message("WARN", "db timeout")),
+            ("other", matching.clone()),
+            ("app", message("ERROR", "db refused")),
+            ("app", message("ERROR", "db timeout")), // 缺少字段
+            ("app", wrong_field),
+            ("app", message("UNKNOWN", "db timeout")),
+        ] {
+            let outcome = set.evaluate(source, &message);
+            assert!(!outcome.drop && outcome.route.is_none() && outcome.tags.is_empty() && outcome.copies.is_empty());
+        }
+        assert!(RuleSet::default().evaluate("app", &matching).route.is_none());
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        for yaml in [
+            "[{ name: r, action: route }]",
+            "[{ name: r, action: copy, destination: ../etc }]",
+            "[{ name: r, action: tag }]",
+            "[{ name: r, action: archive }]",
+            "[{ name: r, match: { levels: [LOUD] }, action: drop }]",
+            "[{ name: r, match: { content: \"(\" }, action: drop }]",
+        ] {
+            let configs: Vec<RuleConfig> = serde_yaml::from_str(yaml).unwrap();
+            assert!(RuleSet::compile(&configs).is_err(), "{}", yaml);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+// 日志注入防护：写入前转义控制字符和ANSI转义序列
+//
+// 恶意内容可以伪造 `[时间] [F]` 行，或者通过终端转义序列劫持运维人员 `tail -f` 的终端。
+// C0/C1控制字符转义为 \xHH / \u{HHHH}，整段ANSI序列以可见形式写出；制表符保留，
+// 换行在多行策略能安全处理时保留，否则同样转义。
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::stats;
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{07}';
+const C1_CSI: char = '\u{9b}';
+
+pub(crate) fn sanitize_content(content: &str, keep_newlines: bool) -> Cow<'_, str> {
+    let needs_escape = |c: char| c.is_control() && c != '\t' && !(keep_newlines && c == '\n');
+    if !content.chars().any(needs_escape) {
+        return Cow::Borrowed(content);
+    }
+
+    let mut sanitized = String::with_capacity(content.len() + 16);
+    let mut controls = 0u64;
+    let mut sequences = 0u64;
+    let mut chars = content.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if !needs_escape(c) {
+            sanitized.push(c);
+            continue;
+        }
+
+        // ANSI序列：ESC [ 参数 终止符 / ESC ] ... BEL|ESC \ / ESC 单字符 / C1 CSI
+        let sequence_end = match c {
+            ESC => match chars.peek().map(|&(_, next)| next) {
+                Some('[') => {
+                    chars.next();
+                    Some(consume_csi(&mut chars, content.len()))
+                }
+                Some(']') => {
+                    chars.next();
+                    Some(consume_osc(&mut chars, content.len()))
+                }
+                Some(next) if ('\u{40}'..='\u{5f}').contains(&next) => {
+                    chars.next().map(|(i, ch)| i + ch.len_utf8())
+                }
+                _ => None,
+            },
+            C1_CSI => Some(consume_csi(&mut chars, content.len())),
+            _ => None,
+        };
+
+        match sequence_end {
+            Some(end) => {
+                sequences += 1;
+                for ch in content[start..end].chars() {
+                    push_escaped(&mut sanitized, ch);
+                }
+            }
+            None => {
+                controls += 1;
+                push_escaped(&mut sanitized, c);
+            }
+        }
+    }
+
+    stats::add(&stats::SANITIZED_CONTROLS, controls);
+    stats::add(&stats::SANITIZED_ANSI, sequences);
+    Cow::Owned(sanitized)
+}
+
+// 把非法UTF-8片段替换为U+FFFD，并计入统计
+pub(crate) fn decode_utf8(payload: &[u8]) -> Cow<'_, str> {
+    if let Ok(text) = std::str::from_utf8(payload) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut text = String::with_capacity(payload.len() + 8);
+    let mut replacements = 0u64;
+    for chunk in payload.utf8_chunks() {
+        text.push_str(chunk.valid());
+        if !chunk.invalid().is_empty() {
+            text.push(char::REPLACEMENT_CHARACTER);
+            replacements += 1;
+        }
+    }
+
+    stats::add(&stats::UTF8_REPLACEMENTS, replacements);
+    Cow::Owned(text)
+}
+
+// CSI: 参数/中间字节 0x20-0x3F，终止字节 0x40-0x7E；返回序列结束位置
+fn consume_csi(chars: &mut Peekable<CharIndices>, len: usize) -> usize {
+    while let Some(&(i, c)) = chars.peek() {
+        match c {
+            '\u{20}'..='\u{3f}' => {
+                chars.next();
+            }
+            '\u{40}'..='\u{7e}' => {
+                chars.next();
+                return i + c.len_utf8();
+            }
+            _ => return i, // 非法序列，截止到此处
+        }
+    }
+    len
+}
+
+// OSC: 以 BEL 或 ESC \ 结束；返回序列结束位置
+fn consume_osc(chars: &mut Peekable<CharIndices>, len: usize) -> usize {
+    while let Some((i, c)) = chars.next() {
+        if c == BEL {
+            return i + c.len_utf8();
+        }
+        if c == ESC && chars.peek().is_some_and(|&(_, next)| next == '\\') {
+            chars.next();
+            return i + 2;
+        }
+    }
+    len
+}
+
+fn push_escaped(out: &mut String, c: char) {
+    if c.is_control() && c != '\t' {
+        let code = c as u32;
+        if code <= 0xff && !('\u{80}'..='\u{9f}').contains(&c) {
+            let _ = write!(out, "\\x{:02x}", code);
+        } else {
+            let _ = write!(out, "\\u{{{:04x}}}", code);
+        }
+    } else {
+        out.push(c);
+    }
+}
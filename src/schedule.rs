@@ -0,0 +1,218 @@
+// 维护任务调度：按cron表达式执行命名任务
+//
+// logging.jobs 中每个任务包含：
+//   name        - 任务名称，用于日志
+//   job         - cleanup（保留期清理、归档和容量清理）/ compress / report / integrity
+//   cron        - cron表达式（见 cron.rs），按配置的时区计算，夏令时切换时不会重复或漏掉执行
+//   jitter_secs - 每次执行前随机延迟 0~N 秒，避免多台主机同时执行
+// 同类任务（即使配置了多个名称）上一次尚未结束时跳过本次并记录警告；cleanup、compress 和 integrity
+// 都会遍历或修改目录树，三者与按需容量清理、磁盘空间紧急清理共用一把锁，不同时执行。cron表达式永远不会执行时（如 2月31日）配置校验失败。
+// 没有配置 cleanup 任务时，按 cleanup_time（默认 01:00）每天执行一次清理，与之前的行为一致。
+use std::collections::HashSet;
+use std::time::Duration;
+
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::{
+    archive, cleanup_old_logs, clock::Clock, cron::CronExpr, layout, maintenance, parse_cleanup_time, quota,
+    retention::Retention, LoggingConfig,
+};
+
+const JOB_CLEANUP: &str = "cleanup";
+const JOB_COMPRESS: &str = "compress";
+const JOB_REPORT: &str = "report";
+const JOB_INTEGRITY: &str = "integrity";
+
+const JOB_KIND_ERROR: &str = "logging.jobs 的 job 只能是 cleanup、compress、report 或 integrity";
+const JOB_NAME_ERROR: &str = "logging.jobs 的 name 不能为空且不能重复";
+
+// 修改或遍历目录树的任务共用（包括定时任务之外的按需容量清理和磁盘空间紧急清理），报告任务单独一把
+static TREE_JOBS: Mutex<()> = Mutex::const_new(());
+static REPORT_JOBS: Mutex<()> = Mutex::const_new(());
+
+// 定时任务之外删除文件的后台清理先等待这把锁，避免与定时的清理、压缩同时处理同一批文件
+pub(crate) async fn tree_jobs_guard() -> MutexGuard<'static, ()> {
+    TREE_JOBS.lock().await
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct JobConfig {
+    pub(crate) name: String,
+    pub(crate) job: String,
+    pub(crate) cron: String,
+    #[serde(default)]
+    pub(crate) jitter_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobKind {
+    Cleanup,
+    Compress,
+    Report,
+    Integrity,
+}
+
+impl JobKind {
+    fn lock(self) -> &'static Mutex<()> {
+        match self {
+            JobKind::Cleanup | JobKind::Compress | JobKind::Integrity => &TREE_JOBS,
+            JobKind::Report => &REPORT_JOBS,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Job {
+    name: String,
+    kind: JobKind,
+    cron: CronExpr,
+    jitter_secs: u64,
+}
+
+// 任务执行需要的上下文
+#[derive(Debug, Clone)]
+pub(crate) struct JobContext {
+    pub(crate) log_path: String,
+    pub(crate) spool_path: Option<String>, // spool策略的备用目录，与日志目录一起清理
+    pub(crate) retention: Retention,
+    pub(crate) archive: archive::ArchiveConfig,
+    pub(crate) clock: Clock,
+    pub(crate) layout: layout::Layout,
+    pub(crate) quota: Option<quota::Quota>,
+}
+
+// 解析并校验任务列表，没有 cleanup 任务时按 cleanup_time 补上
+pub(crate) fn build_jobs(logging: &LoggingConfig) -> Result<Vec<Job>, Box<dyn std::error::Error>> {
+    let clock = Clock::new(&logging.time)?;
+    let now = clock.now().to_utc();
+    let mut jobs = Vec::new();
+    let mut names = HashSet::new();
+    for config in &logging.jobs {
+        if config.name.trim().is_empty() || !names.insert(config.name.as_str()) {
+            return Err(JOB_NAME_ERROR.into());
+        }
+        let kind = match config.job.as_str() {
+            JOB_CLEANUP => JobKind::Cleanup,
+            JOB_COMPRESS => JobKind::Compress,
+            JOB_REPORT => JobKind::Report,
+            JOB_INTEGRITY => JobKind::Integrity,
+            _ => return Err(JOB_KIND_ERROR.into()),
+        };
+        let cron = CronExpr::parse(&config.cron).map_err(|e| format!("logging.jobs[{}]: {}", config.name, e))?;
+        if cron.next_after(now, &clock).is_none() {
+            return Err(format!("logging.jobs[{}]: cron表达式永远不会执行: {}", config.name, config.cron).into());
+        }
+        jobs.push(Job {
+            name: config.name.clone(),
+            kind,
+            cron,
+            jitter_secs: config.jitter_secs,
+        });
+    }
+
+    if !jobs.iter().any(|job| job.kind == JobKind::Cleanup) {
+        let (hour, minute, second) = match logging.cleanup_time {
+            Some(ref time) => parse_cleanup_time(time)?,
+            None => (1, 0, 0), // 默认凌晨1点
+        };
+        jobs.push(Job {
+            name: JOB_CLEANUP.to_string(),
+            kind: JobKind::Cleanup,
+            cron: CronExpr::parse(&format!("{} {} {} * * *", second, minute, hour))?,
+            jitter_secs: 0,
+        });
+    }
+
+    Ok(jobs)
+}
+
+pub(crate) fn start(jobs: Vec<Job>, context: JobContext) {
+    for job in jobs {
+        tokio::spawn(run_job(job, context.clone()));
+    }
+}
+
+async fn run_job(job: Job, context: JobContext) {
+    let clock = &context.clock;
+    let mut after = clock.now().to_utc();
+
+    loop {
+        let Some(next) = job.cron.next_after(after, clock) else {
+            tklog::async_error!("schedule|", &format!("任务 {} 找不到下一次执行时间，已停止", job.name));
+            return;
+        };
+        tklog::async_info!(
+            "schedule|",
+            &format!(
+                "任务 {} 下次执行时间: {}",
+                job.name,
+                clock.convert(next).format("%Y-%m-%d %H:%M:%S %:z")
+            )
+        );
+
+        // 完整等待到执行时间（含不足1秒的部分），避免提前醒来后重复执行
+        if let Ok(delay) = (next - clock.now().to_utc()).to_std() {
+            tokio::time::sleep(delay).await;
+        }
+        if job.jitter_secs > 0 {
+            tokio::time::sleep(Duration::from_secs(fastrand::u64(0..=job.jitter_secs))).await;
+        }
+        // 下一次从本次计划时间之后计算；执行被推迟太久时从当前时间计算，不补跑
+        after = next.max(clock.now().to_utc());
+
+        match job.kind.lock().try_lock() {
+            Ok(guard) => {
+                let (name, kind, context) = (job.name.clone(), job.kind, context.clone());
+                tokio::spawn(async move {
+                    tklog::async_info!("schedule|", &format!("任务 {} 开始执行", name));
+                    run_once(kind, &context).await;
+                    tklog::async_info!("schedule|", &format!("任务 {} 执行结束", name));
+                    drop(guard);
+                });
+            }
+            Err(_) => {
+                tklog::async_warn!(
+                    "schedule|",
+                    &format!("任务 {} 的同类任务仍在执行，跳过本次", job.name)
+                );
+            }
+        }
+    }
+}
+
+async fn run_once(kind: JobKind, context: &JobContext) {
+    let JobContext {
+        log_path,
+        spool_path,
+        retention,
+        archive,
+        clock,
+        layout,
+        quota,
+    } = context;
+
+    match kind {
+        JobKind::Cleanup => {
+            cleanup_old_logs(log_path, spool_path.as_deref(), retention, archive, clock, layout).await;
+            if let Some(quota) = quota {
+                quota::enforce(log_path, clock, layout, quota, retention, archive).await;
+            }
+        }
+        JobKind::Compress => maintenance::compress_sweep(log_path, clock, layout).await,
+        JobKind::Report => maintenance::storage_report(log_path, layout).await,
+        JobKind::Integrity => maintenance::integrity_check(log_path, clock, layout).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn never_firing_jobs_are_rejected() {
+        let logging = testutil::logging(r#"jobs: [{ name: never, job: report, cron: "0 0 31 2 *" }]"#);
+        let error = build_jobs(&logging).unwrap_err().to_string();
+        assert!(error.contains("永远不会执行"), "{}", error);
+    }
+}
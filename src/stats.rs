@@ -0,0 +1,102 @@
+// 运行统计：进程级计数器，随消息统计一起输出
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// 写入前清洗的统计
+pub(crate) static SANITIZED_CONTROLS: AtomicU64 = AtomicU64::new(0); // 转义的控制字符
+pub(crate) static SANITIZED_ANSI: AtomicU64 = AtomicU64::new(0); // 转义的ANSI转义序列
+pub(crate) static UTF8_REPLACEMENTS: AtomicU64 = AtomicU64::new(0); // 替换的非法UTF-8片段
+
+// 磁盘空间保护的统计
+pub(crate) static DISK_DROPPED: AtomicU64 = AtomicU64::new(0); // 空间不足时丢弃的低级别日志
+pub(crate) static DISK_SPOOLED: AtomicU64 = AtomicU64::new(0); // 空间不足时写入备用目录的日志
+
+// 按偏移量检查点跳过的重复写入
+pub(crate) static DEDUPLICATED: AtomicU64 = AtomicU64::new(0);
+
+// fsync 延迟直方图：各桶上限（毫秒），最后一桶为超过最大上限的次数
+const FSYNC_BUCKETS_MS: [u64; 6] = [1, 5, 10, 50, 100, 500];
+static FSYNC_HISTOGRAM: [AtomicU64; 7] = [const { AtomicU64::new(0) }; 7];
+static FSYNC_MAX_US: AtomicU64 = AtomicU64::new(0);
+
+// 按来源（主题）统计的超限记录数
+static OVERSIZE: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+pub(crate) fn record_oversize(source: &str) {
+    if let Ok(mut oversize) = OVERSIZE.lock() {
+        *oversize.entry(source.to_string()).or_insert(0) += 1;
+    }
+}
+
+pub(crate) fn record_fsync(elapsed: Duration) {
+    let micros = elapsed.as_micros() as u64;
+    let bucket = FSYNC_BUCKETS_MS
+        .iter()
+        .position(|limit| micros <= limit * 1000)
+        .unwrap_or(FSYNC_BUCKETS_MS.len());
+    FSYNC_HISTOGRAM[bucket].fetch_add(1, Ordering::Relaxed);
+    FSYNC_MAX_US.fetch_max(micros, Ordering::Relaxed);
+}
+
+pub(crate) fn add(counter: &AtomicU64, n: u64) {
+    if n > 0 {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+// 统计摘要，格式: 名称 数量, ...
+pub(crate) fn summary() -> String {
+    let mut summary = format!(
+        "控制字符转义 {}，ANSI序列转义 {}，UTF-8替换 {}",
+        SANITIZED_CONTROLS.load(Ordering::Relaxed),
+        SANITIZED_ANSI.load(Ordering::Relaxed),
+        UTF8_REPLACEMENTS.load(Ordering::Relaxed)
+    );
+
+    if let Some(disk) = crate::disk::describe() {
+        summary.push_str(&format!(
+            "，{}，空间不足丢弃 {}，转存 {}",
+            disk,
+            DISK_DROPPED.load(Ordering::Relaxed),
+            DISK_SPOOLED.load(Ordering::Relaxed)
+        ));
+    }
+
+    let deduplicated = DEDUPLICATED.load(Ordering::Relaxed);
+    if deduplicated > 0 {
+        summary.push_str(&format!("，重复投递跳过 {}", deduplicated));
+    }
+
+    let fsync: Vec<u64> = FSYNC_HISTOGRAM.iter().map(|n| n.load(Ordering::Relaxed)).collect();
+    let fsync_total: u64 = fsync.iter().sum();
+    if fsync_total > 0 {
+        let mut buckets: Vec<String> = FSYNC_BUCKETS_MS
+            .iter()
+            .zip(&fsync)
+            .map(|(limit, n)| format!("≤{}ms={}", limit, n))
+            .collect();
+        buckets.push(format!(
+            ">{}ms={}",
+            FSYNC_BUCKETS_MS[FSYNC_BUCKETS_MS.len() - 1],
+            fsync[FSYNC_BUCKETS_MS.len()]
+        ));
+        summary.push_str(&format!(
+            "，fsync {}次（{}，最大 {}us）",
+            fsync_total,
+            buckets.join(", "),
+            FSYNC_MAX_US.load(Ordering::Relaxed)
+        ));
+    }
+
+    if let Ok(oversize) = OVERSIZE.lock() {
+        if !oversize.is_empty() {
+            let per_source: Vec<String> =
+                oversize.iter().map(|(source, n)| format!("{}={}", source, n)).collect();
+            summary.push_str(&format!("，超限: {}", per_source.join(", ")));
+        }
+    }
+
+    summary
+}
@@ -0,0 +1,72 @@
+// 测试共用的构造工具：Kafka记录、内联YAML的日志配置，以及离开作用域时自动删除的临时目录
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{KafkaRecord, LoggingConfig};
+
+// 日志配置的必填项，测试只需写出关心的部分
+const BASE_LOGGING: &str = "{ level: INFO, compress: false, rotate: hour, retention_days: 7 }";
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// 只有主题、分区、偏移量和载荷的记录，其他字段按需在返回后修改
+pub(crate) fn record(topic: &str, partition: i32, offset: i64, payload: &[u8]) -> KafkaRecord {
+    KafkaRecord {
+        topic: topic.to_string(),
+        partition,
+        offset,
+        timestamp_ms: 0,
+        key: None,
+        headers: Vec::new(),
+        payload: payload.to_vec(),
+    }
+}
+
+// 在必填项上合并 yaml 中的配置
+pub(crate) fn logging(yaml: &str) -> LoggingConfig {
+    let mut value: serde_yaml::Value = serde_yaml::from_str(BASE_LOGGING).unwrap();
+    if !yaml.trim().is_empty() {
+        let extra: serde_yaml::Mapping = serde_yaml::from_str(yaml).unwrap();
+        value.as_mapping_mut().unwrap().extend(extra);
+    }
+    serde_yaml::from_value(value).unwrap()
+}
+
+// 异步测试在开始时调用：关闭tklog的输出。异步日志的接收任务启动在第一次记录日志的运行时中，
+// 每个 #[tokio::test] 有自己的运行时，那个测试结束后其他测试记录日志会失败
+pub(crate) fn silence_logging() {
+    let log = tklog::ASYNC_LOG;
+    log.set_level(tklog::LEVEL::Off);
+}
+
+// 每个测试独立的临时目录，离开作用域时（包括断言失败）删除
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "log_server_{}_{}_{}",
+            name,
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub(crate) fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
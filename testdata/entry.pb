@@ -0,0 +1,3 @@
+
+ERRORboom
+billing*
\ No newline at end of file
@@ -0,0 +1,4 @@
+
+WARN
+slow queryapi"web-1:2026-10-18T12:00:00ZB
+regioneu
\ No newline at end of file